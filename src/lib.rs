@@ -5,6 +5,8 @@ extern crate cranelift_entity;
 extern crate cranelift_native;
 extern crate cranelift_wasm;
 extern crate libc;
+#[macro_use]
+extern crate log;
 extern crate memmap;
 extern crate region;
 extern crate structopt;
@@ -14,6 +16,7 @@ extern crate wasmparser;
 extern crate target_lexicon;
 pub extern crate nix; // re-exported for usage in macros
 extern crate rayon;
+extern crate sha2;
 
 #[macro_use]
 mod macros;
@@ -19,8 +19,3 @@ macro_rules! include_wast2wasm_bytes {
     }};
 }
 
-#[macro_export]
-macro_rules! debug {
-    ($fmt:expr) => (if cfg!(debug_assertions) { println!(concat!("Wasmer::", $fmt)) });
-    ($fmt:expr, $($arg:tt)*) => (if cfg!(debug_assertions) { println!(concat!("Wasmer::", $fmt, "\n"), $($arg)*) });
-}
\ No newline at end of file
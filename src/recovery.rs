@@ -32,9 +32,7 @@ macro_rules! call_protected {
         unsafe {
             use crate::recovery::{setjmp, SETJMP_BUFFER, SIGHANDLER_INIT};
             use crate::sighandler::install_sighandler;
-            use crate::webassembly::ErrorKind;
-
-            use crate::nix::sys::signal::{Signal, SIGBUS, SIGFPE, SIGILL, SIGSEGV};
+            use crate::webassembly::{ErrorKind, TrapCode};
 
             let jmp_buf = SETJMP_BUFFER.with(|buf| buf.get());
             let prev_jmp_buf = *jmp_buf;
@@ -46,15 +44,8 @@ macro_rules! call_protected {
             let signum = setjmp(jmp_buf as *mut ::nix::libc::c_void);
             if signum != 0 {
                 *jmp_buf = prev_jmp_buf;
-                let signal = match Signal::from_c_int(signum) {
-                    Ok(SIGFPE) => "floating-point exception",
-                    Ok(SIGILL) => "illegal instruction",
-                    Ok(SIGSEGV) => "segmentation violation",
-                    Ok(SIGBUS) => "bus error",
-                    Err(_) => "error while getting the Signal",
-                    _ => "unkown trapped signal",
-                };
-                Err(ErrorKind::RuntimeError(format!("trap - {}", signal)))
+                let code = TrapCode::from_signal(signum);
+                Err(ErrorKind::Trap(code, code.message()))
             } else {
                 let ret = $x; // TODO: Switch stack?
                 *jmp_buf = prev_jmp_buf;
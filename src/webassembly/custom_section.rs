@@ -0,0 +1,115 @@
+//! Access to the custom sections of a WebAssembly binary, for the
+//! sections that the translation to Cranelift IR ignores (like `dylink.0`
+//! or `linking`).
+use super::errors::ErrorKind;
+
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// A reader for the binary encoding of the custom sections.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    pub fn eof(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    /// Returns the bytes that haven't been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position.min(self.data.len())..]
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, ErrorKind> {
+        let byte = *self.data.get(self.position).ok_or_else(|| {
+            ErrorKind::CompileError("unexpected end of the custom section".to_string())
+        })?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub fn read_var_u32(&mut self) -> Result<u32, ErrorKind> {
+        let mut result: u32 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            result |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+            if shift > 28 {
+                return Err(ErrorKind::CompileError(
+                    "invalid LEB128 integer in the custom section".to_string(),
+                ));
+            }
+        }
+    }
+
+    pub fn read_var_i32(&mut self) -> Result<i32, ErrorKind> {
+        let mut result: i32 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            result |= i32::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 32 && byte & 0x40 != 0 {
+                    // Sign extend the result
+                    result |= !0 << shift;
+                }
+                return Ok(result);
+            }
+            if shift > 28 {
+                return Err(ErrorKind::CompileError(
+                    "invalid LEB128 integer in the custom section".to_string(),
+                ));
+            }
+        }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ErrorKind> {
+        if len > self.data.len() - self.position {
+            return Err(ErrorKind::CompileError(
+                "unexpected end of the custom section".to_string(),
+            ));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, ErrorKind> {
+        let len = self.read_var_u32()? as usize;
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| {
+            ErrorKind::CompileError("invalid UTF-8 string in the custom section".to_string())
+        })
+    }
+}
+
+/// Returns the name and the payload of every custom section of the
+/// WebAssembly binary, in order.
+pub fn custom_sections(buffer: &[u8]) -> Result<Vec<(String, &[u8])>, ErrorKind> {
+    let mut sections = Vec::new();
+    let mut reader = Reader::new(buffer);
+    // Skip the magic number and the version
+    reader.read_bytes(8)?;
+    while !reader.eof() {
+        let section_id = reader.read_u8()?;
+        let len = reader.read_var_u32()? as usize;
+        let section = reader.read_bytes(len)?;
+        if section_id != CUSTOM_SECTION_ID {
+            continue;
+        }
+        let mut section = Reader::new(section);
+        let name = section.read_string()?;
+        sections.push((name, section.remaining()));
+    }
+    Ok(sections)
+}
@@ -0,0 +1,115 @@
+//! Parsing of the `dylink.0` custom section that Emscripten emits in
+//! side modules. It describes what the module needs from the dynamic
+//! linker that loads it.
+//! See https://github.com/WebAssembly/tool-conventions/blob/master/DynamicLinking.md
+use super::custom_section::{custom_sections, Reader};
+use super::errors::ErrorKind;
+
+const DYLINK_SECTION_NAME: &str = "dylink.0";
+// The section used by older toolchains, without subsections
+const LEGACY_DYLINK_SECTION_NAME: &str = "dylink";
+
+const WASM_DYLINK_MEM_INFO: u8 = 1;
+const WASM_DYLINK_NEEDED: u8 = 2;
+
+/// The requirements of a side module, as declared in its `dylink.0` section.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DylinkInfo {
+    /// Bytes of linear memory the module needs for its static data
+    pub mem_size: u32,
+    /// Required alignment of the module memory, as a power of 2
+    pub mem_align: u32,
+    /// Number of table elements the module needs
+    pub table_size: u32,
+    /// Required alignment of the module table elements, as a power of 2
+    pub table_align: u32,
+    /// The dynamic libraries the module depends on
+    pub needed: Vec<String>,
+}
+
+fn read_mem_info(reader: &mut Reader, info: &mut DylinkInfo) -> Result<(), ErrorKind> {
+    info.mem_size = reader.read_var_u32()?;
+    info.mem_align = reader.read_var_u32()?;
+    info.table_size = reader.read_var_u32()?;
+    info.table_align = reader.read_var_u32()?;
+    Ok(())
+}
+
+fn read_needed(reader: &mut Reader, info: &mut DylinkInfo) -> Result<(), ErrorKind> {
+    let count = reader.read_var_u32()?;
+    for _ in 0..count {
+        info.needed.push(reader.read_string()?);
+    }
+    Ok(())
+}
+
+fn parse_dylink_0(payload: &[u8]) -> Result<DylinkInfo, ErrorKind> {
+    let mut info = DylinkInfo::default();
+    let mut reader = Reader::new(payload);
+    while !reader.eof() {
+        let subsection_type = reader.read_u8()?;
+        let len = reader.read_var_u32()? as usize;
+        let mut subsection = Reader::new(reader.read_bytes(len)?);
+        match subsection_type {
+            WASM_DYLINK_MEM_INFO => read_mem_info(&mut subsection, &mut info)?,
+            WASM_DYLINK_NEEDED => read_needed(&mut subsection, &mut info)?,
+            // The export and import info subsections are not needed for now
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+fn parse_legacy_dylink(payload: &[u8]) -> Result<DylinkInfo, ErrorKind> {
+    let mut info = DylinkInfo::default();
+    let mut reader = Reader::new(payload);
+    read_mem_info(&mut reader, &mut info)?;
+    read_needed(&mut reader, &mut info)?;
+    Ok(info)
+}
+
+/// Returns the contents of the `dylink.0` (or legacy `dylink`) section of
+/// the WebAssembly binary, or `None` if it's not a side module.
+pub fn parse_dylink_info(buffer: &[u8]) -> Result<Option<DylinkInfo>, ErrorKind> {
+    for (name, payload) in custom_sections(buffer)? {
+        if name == DYLINK_SECTION_NAME {
+            return parse_dylink_0(payload).map(Some);
+        } else if name == LEGACY_DYLINK_SECTION_NAME {
+            return parse_legacy_dylink(payload).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_dylink_info, DylinkInfo};
+
+    const HEADER: &[u8] = &[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn test_parse_dylink_0() {
+        let mut buffer = HEADER.to_vec();
+        buffer.extend_from_slice(&[
+            0x00, 0x17, // custom section, 23 bytes
+            0x08, b'd', b'y', b'l', b'i', b'n', b'k', b'.', b'0',
+            0x01, 0x05, 0x80, 0x01, 0x04, 0x02, 0x00, // mem info
+            0x02, 0x05, 0x01, 0x03, b'l', b'i', b'b', // needed
+        ]);
+        assert_eq!(
+            parse_dylink_info(&buffer).unwrap(),
+            Some(DylinkInfo {
+                mem_size: 128,
+                mem_align: 4,
+                table_size: 2,
+                table_align: 0,
+                needed: vec!["lib".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_no_dylink() {
+        assert_eq!(parse_dylink_info(HEADER).unwrap(), None);
+    }
+}
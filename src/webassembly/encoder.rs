@@ -0,0 +1,408 @@
+//! Encoding of a parsed `Module` back to the WebAssembly binary format.
+//! Only the sections known by the engine are emitted (custom sections,
+//! like the names, are dropped), so it can be used for normalizing modules.
+use cranelift_codegen::ir::{self, types};
+use cranelift_entity::EntityRef;
+use cranelift_wasm::{Global, GlobalIndex, GlobalInit, Memory, Table};
+
+use super::errors::ErrorKind;
+use super::module::{Export, Module};
+
+const WASM_MAGIC: &[u8] = &[0x00, 0x61, 0x73, 0x6d];
+const WASM_VERSION: &[u8] = &[0x01, 0x00, 0x00, 0x00];
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_TABLE: u8 = 4;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_START: u8 = 8;
+const SECTION_ELEMENT: u8 = 9;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+
+const EXTERNAL_FUNCTION: u8 = 0x00;
+const EXTERNAL_TABLE: u8 = 0x01;
+const EXTERNAL_MEMORY: u8 = 0x02;
+const EXTERNAL_GLOBAL: u8 = 0x03;
+
+const TYPE_FUNC: u8 = 0x60;
+const TYPE_ANYFUNC: u8 = 0x70;
+
+const OP_END: u8 = 0x0b;
+const OP_GET_GLOBAL: u8 = 0x23;
+const OP_I32_CONST: u8 = 0x41;
+const OP_I64_CONST: u8 = 0x42;
+const OP_F32_CONST: u8 = 0x43;
+const OP_F64_CONST: u8 = 0x44;
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn var_u32(&mut self, mut value: u32) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.u8(byte);
+                return;
+            }
+            self.u8(byte | 0x80);
+        }
+    }
+
+    fn var_i64(&mut self, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            let sign_bit = byte & 0x40 != 0;
+            if (value == 0 && !sign_bit) || (value == -1 && sign_bit) {
+                self.u8(byte);
+                return;
+            }
+            self.u8(byte | 0x80);
+        }
+    }
+
+    fn len(&mut self, len: usize) {
+        self.var_u32(len as u32);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+    }
+
+    fn value_type(&mut self, ty: ir::Type) -> Result<(), ErrorKind> {
+        self.u8(match ty {
+            types::I32 => 0x7f,
+            types::I64 => 0x7e,
+            types::F32 => 0x7d,
+            types::F64 => 0x7c,
+            _ => {
+                return Err(ErrorKind::EncodeError(format!(
+                    "unsupported WebAssembly type {}",
+                    ty
+                )))
+            }
+        });
+        Ok(())
+    }
+
+    fn limits(&mut self, minimum: usize, maximum: Option<usize>) {
+        match maximum {
+            Some(maximum) => {
+                self.u8(0x01);
+                self.len(minimum);
+                self.len(maximum);
+            }
+            None => {
+                self.u8(0x00);
+                self.len(minimum);
+            }
+        }
+    }
+
+    fn table_type(&mut self, table: &Table) {
+        // Only the MVP `anyfunc` tables are supported
+        self.u8(TYPE_ANYFUNC);
+        self.limits(table.size, table.maximum);
+    }
+
+    fn memory_type(&mut self, memory: &Memory) {
+        self.limits(memory.pages_count, memory.maximum);
+    }
+
+    fn global_type(&mut self, global: &Global) -> Result<(), ErrorKind> {
+        self.value_type(global.ty)?;
+        self.u8(global.mutability as u8);
+        Ok(())
+    }
+
+    /// Writes the offset expression of an element or data segment
+    fn offset_expr(&mut self, base: Option<GlobalIndex>, offset: usize) {
+        match base {
+            Some(global_index) => {
+                self.u8(OP_GET_GLOBAL);
+                self.len(global_index.index());
+            }
+            None => {
+                self.u8(OP_I32_CONST);
+                self.var_i64(i64::from(offset as u32 as i32));
+            }
+        }
+        self.u8(OP_END);
+    }
+
+    fn section(&mut self, id: u8, count: usize, contents: Writer) {
+        if count == 0 {
+            return;
+        }
+        let mut section = Writer::default();
+        section.len(count);
+        section.bytes.extend_from_slice(&contents.bytes);
+        self.u8(id);
+        self.bytes(&section.bytes);
+    }
+}
+
+/// Encodes the module to the WebAssembly binary format. Fails if the module
+/// uses something that has no binary encoding (e.g. a type not supported by
+/// WebAssembly, for modules modified after parsing).
+pub fn encode_module(module: &Module) -> Result<Vec<u8>, ErrorKind> {
+    let info = &module.info;
+    let mut out = Writer::default();
+    out.bytes.extend_from_slice(WASM_MAGIC);
+    out.bytes.extend_from_slice(WASM_VERSION);
+
+    let mut section = Writer::default();
+    for signature in &info.signatures {
+        section.u8(TYPE_FUNC);
+        section.len(signature.params.len());
+        for param in &signature.params {
+            section.value_type(param.value_type)?;
+        }
+        section.len(signature.returns.len());
+        for ret in &signature.returns {
+            section.value_type(ret.value_type)?;
+        }
+    }
+    out.section(SECTION_TYPE, info.signatures.len(), section);
+
+    let mut section = Writer::default();
+    let mut count = 0;
+    for (func, (module_name, field)) in info.functions.values().zip(&info.imported_funcs) {
+        section.string(module_name);
+        section.string(field);
+        section.u8(EXTERNAL_FUNCTION);
+        section.len(func.entity.index());
+        count += 1;
+    }
+    for table in &info.tables {
+        if let Some((ref module_name, ref field)) = table.import_name {
+            section.string(module_name);
+            section.string(field);
+            section.u8(EXTERNAL_TABLE);
+            section.table_type(&table.entity);
+            count += 1;
+        }
+    }
+    for memory in &info.memories {
+        if let Some((ref module_name, ref field)) = memory.import_name {
+            section.string(module_name);
+            section.string(field);
+            section.u8(EXTERNAL_MEMORY);
+            section.memory_type(&memory.entity);
+            count += 1;
+        }
+    }
+    for global in &info.globals {
+        if let Some((ref module_name, ref field)) = global.import_name {
+            section.string(module_name);
+            section.string(field);
+            section.u8(EXTERNAL_GLOBAL);
+            section.global_type(&global.entity)?;
+            count += 1;
+        }
+    }
+    out.section(SECTION_IMPORT, count, section);
+
+    let mut section = Writer::default();
+    let local_funcs: Vec<_> = info
+        .functions
+        .values()
+        .skip(info.imported_funcs.len())
+        .collect();
+    for func in &local_funcs {
+        section.len(func.entity.index());
+    }
+    out.section(SECTION_FUNCTION, local_funcs.len(), section);
+
+    let mut section = Writer::default();
+    let mut count = 0;
+    for table in info.tables.iter().filter(|t| t.import_name.is_none()) {
+        section.table_type(&table.entity);
+        count += 1;
+    }
+    out.section(SECTION_TABLE, count, section);
+
+    let mut section = Writer::default();
+    let mut count = 0;
+    for memory in info.memories.iter().filter(|m| m.import_name.is_none()) {
+        section.memory_type(&memory.entity);
+        count += 1;
+    }
+    out.section(SECTION_MEMORY, count, section);
+
+    let mut section = Writer::default();
+    let mut count = 0;
+    for global in info.globals.iter().filter(|g| g.import_name.is_none()) {
+        section.global_type(&global.entity)?;
+        match global.entity.initializer {
+            GlobalInit::I32Const(value) => {
+                section.u8(OP_I32_CONST);
+                section.var_i64(i64::from(value));
+            }
+            GlobalInit::I64Const(value) => {
+                section.u8(OP_I64_CONST);
+                section.var_i64(value);
+            }
+            GlobalInit::F32Const(bits) => {
+                section.u8(OP_F32_CONST);
+                for i in 0..4 {
+                    section.u8((bits >> (8 * i)) as u8);
+                }
+            }
+            GlobalInit::F64Const(bits) => {
+                section.u8(OP_F64_CONST);
+                for i in 0..8 {
+                    section.u8((bits >> (8 * i)) as u8);
+                }
+            }
+            GlobalInit::GlobalRef(global_index) => {
+                section.u8(OP_GET_GLOBAL);
+                section.len(global_index.index());
+            }
+            GlobalInit::Import() => {
+                return Err(ErrorKind::EncodeError(
+                    "local global initialized as an import".to_string(),
+                ))
+            }
+        }
+        section.u8(OP_END);
+        count += 1;
+    }
+    out.section(SECTION_GLOBAL, count, section);
+
+    // The exports are emitted in declaration order. Those added after parsing
+    // (so missing from `export_order`) follow by name, to stay deterministic.
+    let mut exports: Vec<(&String, &Export)> = info
+        .export_order
+        .iter()
+        .filter_map(|name| info.exports.get(name).map(|export| (name, export)))
+        .collect();
+    let mut added: Vec<(&String, &Export)> = info
+        .exports
+        .iter()
+        .filter(|(name, _)| !info.export_order.contains(*name))
+        .collect();
+    added.sort_by(|(a, _), (b, _)| a.cmp(b));
+    exports.extend(added);
+    let mut section = Writer::default();
+    for (name, export) in &exports {
+        section.string(name);
+        let (kind, index) = match export {
+            Export::Function(index) => (EXTERNAL_FUNCTION, index.index()),
+            Export::Table(index) => (EXTERNAL_TABLE, index.index()),
+            Export::Memory(index) => (EXTERNAL_MEMORY, index.index()),
+            Export::Global(index) => (EXTERNAL_GLOBAL, index.index()),
+        };
+        section.u8(kind);
+        section.len(index);
+    }
+    out.section(SECTION_EXPORT, exports.len(), section);
+
+    if let Some(start_func) = info.start_func {
+        out.u8(SECTION_START);
+        let mut section = Writer::default();
+        section.len(start_func.index());
+        out.bytes(&section.bytes);
+    }
+
+    let mut section = Writer::default();
+    for segment in &info.table_elements {
+        section.len(segment.table_index.index());
+        section.offset_expr(segment.base, segment.offset);
+        section.len(segment.elements.len());
+        for func_index in &segment.elements {
+            section.len(func_index.index());
+        }
+    }
+    out.section(SECTION_ELEMENT, info.table_elements.len(), section);
+
+    let mut section = Writer::default();
+    for body in &module.func_bytecode {
+        section.bytes(body);
+    }
+    out.section(SECTION_CODE, module.func_bytecode.len(), section);
+
+    let mut section = Writer::default();
+    for init in &info.data_initializers {
+        section.len(init.memory_index.index());
+        section.offset_expr(init.base, init.offset);
+        section.bytes(&init.data);
+    }
+    out.section(SECTION_DATA, info.data_initializers.len(), section);
+
+    Ok(out.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::compile;
+    use super::super::errors::ErrorKind;
+    use cranelift_codegen::ir::types;
+    use wabt::wat2wasm;
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "print" (func $print (param i32)))
+              (memory 1 2)
+              (table 2 anyfunc)
+              (global $g (mut i64) (i64.const -5))
+              (func $add (export "add") (param i32 i32) (result i32)
+                get_local 0
+                get_local 1
+                i32.add)
+              (elem (i32.const 1) $add)
+              (data (i32.const 8) "hello"))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let binary = module.to_binary().unwrap();
+        let roundtrip = compile(binary.clone()).unwrap();
+        assert_eq!(roundtrip.to_binary().unwrap(), binary);
+    }
+
+    #[test]
+    fn test_exports_in_declaration_order() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func (export "zeta"))
+              (func (export "alpha"))
+              (memory (export "memory") 1))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let roundtrip = compile(module.to_binary().unwrap()).unwrap();
+        assert_eq!(roundtrip.info.export_order, vec!["zeta", "alpha", "memory"]);
+    }
+
+    #[test]
+    fn test_unsupported_type() {
+        let wasm = wat2wasm("(module (func (param i32)))").unwrap();
+        let mut module = compile(wasm).unwrap();
+        module.info.signatures[0].params[0].value_type = types::B1;
+        match module.to_binary() {
+            Err(ErrorKind::EncodeError(_)) => {}
+            other => panic!("Expected an encoding error, received {:?}", other),
+        }
+    }
+}
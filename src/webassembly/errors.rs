@@ -10,6 +10,9 @@
 //! RuntimeError object — the type that is thrown whenever WebAssembly
 //!  specifies a trap.
 
+use super::trap::TrapCode;
+use std::io;
+
 error_chain! {
     errors {
         CompileError(reason: String) {
@@ -26,5 +29,50 @@ error_chain! {
             description("WebAssembly runtime error")
             display("Runtime error: {}", reason)
         }
+
+        IoError(path: String, kind: io::ErrorKind, reason: String) {
+            description("WebAssembly module I/O error")
+            display("I/O error: {}: {}", path, reason)
+        }
+
+        CodeBudgetExceeded(max_code_bytes: usize) {
+            description("WebAssembly code budget exceeded")
+            display("Compilation error: the compiled code exceeds the budget of {} bytes", max_code_bytes)
+        }
+
+        CompilationCancelled {
+            description("WebAssembly compilation cancelled")
+            display("Compilation error: the compilation was cancelled")
+        }
+
+        NoMemory {
+            description("WebAssembly memory access without a memory")
+            display("Compilation error: a function accesses a memory, but the module has none")
+        }
+
+        ExportIndexOutOfRange(name: String, index: usize) {
+            description("WebAssembly export index out of range")
+            display("Compilation error: the export {} points to the nonexistent index {}", name, index)
+        }
+
+        EncodeError(reason: String) {
+            description("WebAssembly encoding error")
+            display("Encoding error: {}", reason)
+        }
+
+        InitializerOrderViolation(reason: String) {
+            description("WebAssembly initializer order violation")
+            display("Link error: {}", reason)
+        }
+
+        PoolExhausted(max_instances: usize) {
+            description("WebAssembly instance pool exhausted")
+            display("Link error: the pool already has {} live instances", max_instances)
+        }
+
+        Trap(code: TrapCode, message: String) {
+            description("WebAssembly trap")
+            display("Runtime error: {}", message)
+        }
     }
 }
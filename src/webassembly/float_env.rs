@@ -0,0 +1,61 @@
+//! Control of the floating point environment of the host. WebAssembly
+//! mandates round-to-nearest-even without flushing the denormals to zero,
+//! but the host (or a native library it loaded) may have changed it.
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+
+/// The rounding control bits of the MXCSR register
+#[cfg(target_arch = "x86_64")]
+const MXCSR_ROUNDING_MASK: u32 = 0x6000;
+/// The flush-to-zero and denormals-are-zero bits of the MXCSR register
+#[cfg(target_arch = "x86_64")]
+const MXCSR_FLUSH_MASK: u32 = 0x8040;
+
+/// The floating point environment of the host, as saved by `enter_strict_float`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FloatEnv(u32);
+
+/// Sets the floating point environment required by WebAssembly and returns
+/// the previous one, to be restored with `restore_float_env`.
+#[cfg(target_arch = "x86_64")]
+pub fn enter_strict_float() -> FloatEnv {
+    unsafe {
+        let mxcsr = _mm_getcsr();
+        _mm_setcsr(mxcsr & !(MXCSR_ROUNDING_MASK | MXCSR_FLUSH_MASK));
+        FloatEnv(mxcsr)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn restore_float_env(env: FloatEnv) {
+    unsafe { _mm_setcsr(env.0) }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn enter_strict_float() -> FloatEnv {
+    FloatEnv(0)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn restore_float_env(_env: FloatEnv) {}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::{enter_strict_float, restore_float_env, MXCSR_ROUNDING_MASK};
+    use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+
+    #[test]
+    fn test_strict_float() {
+        let original = unsafe { _mm_getcsr() };
+        // Round toward zero
+        unsafe { _mm_setcsr(original | MXCSR_ROUNDING_MASK) };
+        let env = enter_strict_float();
+        assert_eq!(unsafe { _mm_getcsr() } & MXCSR_ROUNDING_MASK, 0);
+        restore_float_env(env);
+        assert_eq!(
+            unsafe { _mm_getcsr() } & MXCSR_ROUNDING_MASK,
+            MXCSR_ROUNDING_MASK
+        );
+        unsafe { _mm_setcsr(original) };
+    }
+}
@@ -3,7 +3,8 @@
 //! or webassembly::Memory objects.
 // Code inspired from: https://stackoverflow.com/a/45795699/1072990
 // Adapted to the Webassembly use case
-use crate::webassembly::LinearMemory;
+use cranelift_codegen::ir;
+use crate::webassembly::{ErrorKind, LinearMemory};
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
@@ -116,6 +117,111 @@ pub enum ImportValue {
     Memory(LinearMemory),
 }
 
+/// The type of an import of a module.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImportKind {
+    Function(ir::Signature),
+    Table {
+        minimum: usize,
+        maximum: Option<usize>,
+    },
+    Memory {
+        minimum: usize,
+        maximum: Option<usize>,
+    },
+    Global {
+        ty: ir::Type,
+        mutable: bool,
+    },
+}
+
+/// An import of a module, as declared in its import section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportDescriptor {
+    pub module: String,
+    pub field: String,
+    pub kind: ImportKind,
+}
+
+/// A skeleton of the import object of a module, listing every import it
+/// requires, for the host to fill in (see `Module::import_template`).
+pub struct ImportObjectTemplate {
+    imports: Vec<ImportDescriptor>,
+    values: Vec<Option<ImportValue>>,
+}
+
+impl ImportObjectTemplate {
+    pub fn new(imports: Vec<ImportDescriptor>) -> Self {
+        let values = imports.iter().map(|_| None).collect();
+        ImportObjectTemplate { imports, values }
+    }
+
+    /// Returns the imports of the module, in declaration order.
+    pub fn imports(&self) -> &[ImportDescriptor] {
+        &self.imports
+    }
+
+    /// Fills in the import `module.field`, failing if the module doesn't
+    /// declare such import or if it declares it with another kind.
+    pub fn set(&mut self, module: &str, field: &str, value: ImportValue) -> Result<(), ErrorKind> {
+        let index = self
+            .imports
+            .iter()
+            .position(|import| import.module == module && import.field == field)
+            .ok_or_else(|| {
+                ErrorKind::LinkError(format!("The module doesn't import {}.{}", module, field))
+            })?;
+        let matches = match (&self.imports[index].kind, &value) {
+            (ImportKind::Function(_), ImportValue::Func(_))
+            | (ImportKind::Table { .. }, ImportValue::Table(_))
+            | (ImportKind::Memory { .. }, ImportValue::Memory(_))
+            | (ImportKind::Global { .. }, ImportValue::Global(_)) => true,
+            _ => false,
+        };
+        if !matches {
+            return Err(ErrorKind::LinkError(format!(
+                "Expected {:?} for {}.{}, received {:?}",
+                self.imports[index].kind, module, field, value
+            )));
+        }
+        self.values[index] = Some(value);
+        Ok(())
+    }
+
+    /// Returns the imports that haven't been filled in yet.
+    pub fn missing(&self) -> Vec<&ImportDescriptor> {
+        self.imports
+            .iter()
+            .zip(self.values.iter())
+            .filter(|(_, value)| value.is_none())
+            .map(|(import, _)| import)
+            .collect()
+    }
+
+    /// Moves the filled in values into an import object for instantiating
+    /// the module, failing with the list of the missing imports if any.
+    pub fn import_object(&mut self) -> Result<ImportObject<&str, &str>, ErrorKind> {
+        let missing = self.missing();
+        if !missing.is_empty() {
+            let names: Vec<String> = missing
+                .iter()
+                .map(|import| format!("{}.{}", import.module, import.field))
+                .collect();
+            return Err(ErrorKind::LinkError(format!(
+                "Missing imports: {}",
+                names.join(", ")
+            )));
+        }
+        let mut import_object = ImportObject::new();
+        for (import, value) in self.imports.iter().zip(self.values.iter_mut()) {
+            if let Some(value) = value.take() {
+                import_object.set(import.module.as_str(), import.field.as_str(), value);
+            }
+        }
+        Ok(import_object)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::ImportObject;
@@ -131,4 +237,38 @@ mod tests {
             ImportValue::Func(x as *const u8)
         );
     }
+
+    #[test]
+    fn test_import_template() {
+        use super::super::compile;
+        use wabt::wat2wasm;
+
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "print" (func (param i32)))
+              (import "env" "offset" (global i32)))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut template = module.import_template();
+        assert_eq!(template.imports().len(), 2);
+        assert!(template.set("env", "missing", ImportValue::Global(0)).is_err());
+        assert!(template.set("env", "offset", ImportValue::Table(vec![])).is_err());
+        template.set("env", "offset", ImportValue::Global(1)).unwrap();
+        match template.import_object() {
+            Err(e) => assert!(e.to_string().contains("env.print")),
+            Ok(_) => panic!("env.print wasn't provided"),
+        }
+
+        fn print(_: i32) {}
+        template
+            .set("env", "print", ImportValue::Func(print as *const u8))
+            .unwrap();
+        let import_object = template.import_object().unwrap();
+        assert_eq!(
+            *import_object.get(&"env", &"offset").unwrap(),
+            ImportValue::Global(1)
+        );
+    }
 }
@@ -6,28 +6,48 @@
 //! synchronously instantiate a given webassembly::Module object. However, the
 //! primary way to get an Instance is through the asynchronous
 //! webassembly::instantiate_streaming() function.
-use cranelift_codegen::ir::{Function, LibCall};
-use cranelift_codegen::isa::TargetIsa;
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, ArgumentPurpose, Ebb, ExtFuncData, ExternalName, Function, Inst, InstBuilder,
+    InstructionData, LibCall, MemFlags, Opcode, Signature, StackSlotKind, TrapCode, Type,
+    Value as IrValue, ValueDef,
+};
+use cranelift_codegen::isa::{self, CallConv, TargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
 use cranelift_codegen::{binemit, Context};
 use cranelift_entity::EntityRef;
-use cranelift_wasm::{FuncIndex, GlobalInit};
+use cranelift_wasm::{DefinedFuncIndex, FuncIndex, GlobalIndex, GlobalInit};
 use rayon::prelude::*;
+use sha2::{Digest, Sha256};
 
 use region;
+use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::iter::Iterator;
-use std::mem::size_of;
-use std::ptr::write_unaligned;
+use std::cell::{Cell, RefCell};
+use std::mem::{self, size_of};
+use std::ops::Range;
+use std::ptr::{read_unaligned, write_unaligned};
 use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use super::super::common::slice::{BoundedSlice, UncheckedSlice};
+use super::super::recovery::do_unwind;
 use super::errors::ErrorKind;
+use super::float_env::{self, FloatEnv};
 use super::import_object::{ImportObject, ImportValue};
 use super::math_intrinsics;
-use super::memory::LinearMemory;
+use super::memory::{LinearMemory, Pod};
 use super::module::{Export, ImportableExportable, Module};
-use super::relocation::{Reloc, RelocSink, RelocationType};
+use super::profile::{ProfileTree, Profiler};
+use super::relocation::{Reloc, RelocSink, Relocation, RelocationType};
+use super::shadow::ShadowHeap;
+use super::utils::{fnv1a_hash, zero_volatile};
+use super::trap;
+use super::value::Value;
 
 type TablesSlice = UncheckedSlice<BoundedSlice<usize>>;
 // TODO: this should be `type MemoriesSlice = UncheckedSlice<UncheckedSlice<u8>>;`, but that crashes for some reason.
@@ -55,21 +75,150 @@ pub fn protect_codebuf(code_buf: &Vec<u8>) -> Result<(), String> {
 fn get_function_addr(
     func_index: &FuncIndex,
     import_functions: &Vec<*const u8>,
-    functions: &Vec<Vec<u8>>,
+    functions: &Vec<*const u8>,
 ) -> *const u8 {
     let index = func_index.index();
     let len = import_functions.len();
     let func_pointer = if index < len {
         import_functions[index]
     } else {
-        (functions[index - len]).as_ptr()
+        functions[index - len]
     };
     func_pointer
 }
 
+/// Checks that the initializer `what` can read the global `global_index`:
+/// only the imported globals among the first `available` ones are
+/// initialized at that point.
+fn check_initializer_global(
+    module: &Module,
+    global_index: GlobalIndex,
+    available: usize,
+    what: &str,
+) -> Result<(), ErrorKind> {
+    let index = global_index.index();
+    match module.info.globals.get(index) {
+        Some(global) if index < available && global.import_name.is_some() => Ok(()),
+        Some(_) => Err(ErrorKind::InitializerOrderViolation(format!(
+            "{} references the global {}, which isn't initialized before it",
+            what, index
+        ))),
+        None => Err(ErrorKind::InitializerOrderViolation(format!(
+            "{} references the global {}, which doesn't exist",
+            what, index
+        ))),
+    }
+}
+
+/// Sorts the `ranges` and merges the overlapping or adjacent ones.
+fn merge_ranges(ranges: &mut Vec<Range<usize>>) {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        if let Some(last) = merged.last_mut() {
+            if range.start <= last.end {
+                last.end = last.end.max(range.end);
+                continue;
+            }
+        }
+        merged.push(range);
+    }
+    *ranges = merged;
+}
+
+/// Resolves the imported functions of the module with the provided import object.
+fn resolve_import_functions(
+    module: &Module,
+    import_object: &ImportObject<&str, &str>,
+    mock_missing_imports: bool,
+) -> Result<Vec<*const u8>, ErrorKind> {
+    let mut import_functions = Vec::with_capacity(module.info.imported_funcs.len());
+    for (module, field) in module.info.imported_funcs.iter() {
+        let imported = import_object.get(&module.as_str(), &field.as_str());
+        let function: *const u8 = match imported {
+            Some(ImportValue::Func(f)) => *f,
+            None => {
+                if mock_missing_imports {
+                    debug!(
+                        "The import {}.{} is not provided, therefore will be mocked.",
+                        module, field
+                    );
+                    mock_fn as *const u8
+                } else {
+                    return Err(ErrorKind::LinkError(format!(
+                        "Imported function {}.{} was not provided in the import_functions",
+                        module, field
+                    )));
+                }
+            }
+            Some(other) => {
+                return Err(ErrorKind::LinkError(format!(
+                    "Expected function import for {}.{}, received {:?}",
+                    module, field, other
+                )))
+            }
+        };
+        trace!("Instance - Import {}.{} resolved to {:?}", module, field, function);
+        import_functions.push(function);
+    }
+    Ok(import_functions)
+}
+
+/// Returns a copy of `code` where every 64-bit little endian immediate within
+/// one of the `ranges` is replaced by the index of the range and the offset
+/// within it, so the code doesn't depend on where the ranges are allocated.
+fn normalize_addresses(code: &[u8], ranges: &[Range<u64>]) -> Vec<u8> {
+    let mut normalized = code.to_vec();
+    let mut position = 0;
+    while position + 8 <= code.len() {
+        let value = (0..8).fold(0u64, |value, i| {
+            value | u64::from(code[position + i]) << (8 * i)
+        });
+        match ranges.iter().position(|range| range.start <= value && value < range.end) {
+            Some(index) => {
+                let placeholder = 0xffff_0000_0000_0000
+                    | (index as u64) << 32
+                    | (value - ranges[index].start);
+                for i in 0..8 {
+                    normalized[position + i] = (placeholder >> (8 * i)) as u8;
+                }
+                position += 8;
+            }
+            None => position += 1,
+        }
+    }
+    normalized
+}
+
+/// Patches the relocation `reloc` of the function at `func_addr`
+/// so it points to `target_func_address`.
+fn apply_relocation(func_addr: *const u8, reloc: &Relocation, target_func_address: isize) {
+    match reloc.reloc {
+        Reloc::Abs8 => unsafe {
+            let reloc_address = func_addr.offset(reloc.offset as isize) as i64;
+            let reloc_addend = reloc.addend;
+            let reloc_abs = target_func_address as i64 + reloc_addend;
+            write_unaligned(reloc_address as *mut i64, reloc_abs);
+        },
+        Reloc::X86PCRel4 => unsafe {
+            let reloc_address = func_addr.offset(reloc.offset as isize) as isize;
+            let reloc_addend = reloc.addend as isize;
+            // TODO: Handle overflow.
+            let reloc_delta_i32 = (target_func_address - reloc_address + reloc_addend) as i32;
+            write_unaligned(reloc_address as *mut i32, reloc_delta_i32);
+        },
+        _ => panic!("unsupported reloc kind"),
+    }
+}
+
 /// An Instance of a WebAssembly module
 /// NOTE: There is an assumption that data_pointers is always the
 ///      first field
+///
+/// A `Module` is never modified by the instances created from it, so it
+/// can be shared between threads (e.g. in an `Arc<Module>`), with every
+/// thread creating and calling its own instances. An instance can be moved
+/// to another thread, but it can't be called from several threads at once.
 #[repr(C)]
 #[derive(Debug)]
 #[repr(C)]
@@ -81,23 +230,98 @@ pub struct Instance {
     // pub tables: Arc<Vec<RwLock<Vec<usize>>>>,
     pub tables: Arc<Vec<Vec<usize>>>,
 
+    /// WebAssembly element segments, with the function addresses
+    /// already resolved
+    element_segments: Vec<Vec<usize>>,
+
     /// WebAssembly linear memory data
     pub memories: Arc<Vec<LinearMemory>>,
 
+    /// WebAssembly data segments
+    data_segments: Vec<Vec<u8>>,
+
+    /// The sorted and disjoint ranges of each memory written by the
+    /// data segments at instantiation
+    initialized_ranges: Vec<Vec<Range<usize>>>,
+
     /// WebAssembly global variable data
     pub globals: Vec<u8>,
 
+    /// The executable allocations holding the compiled functions
+    code_buffers: Vec<Vec<u8>>,
+
     /// Webassembly functions
-    // functions: Vec<usize>,
-    functions: Vec<Vec<u8>>,
+    functions: Vec<*const u8>,
+
+    /// The size in bytes of the machine code of each of the local functions
+    code_sizes: Vec<usize>,
 
     /// Imported functions
     import_functions: Vec<*const u8>,
 
+    /// The relocations of the local functions that point to
+    /// imported functions, kept for replacing the imports
+    import_relocations: Vec<(FuncIndex, Relocation)>,
+
+    /// The time spent compiling each of the local functions
+    compile_times: Vec<Duration>,
+
+    /// The stack frame information of each of the local functions
+    frame_infos: Vec<FrameInfo>,
+
+    /// The bounded slices pointed by `data_pointers`. We keep them
+    /// around so the pointers stay valid and the memory bounds
+    /// can be updated when a memory grows.
+    tables_pointer: Vec<BoundedSlice<usize>>,
+    memories_pointer: Vec<BoundedSlice<u8>>,
+
     /// The module start function
     pub start_func: Option<FuncIndex>,
     // Region start memory location
     // code_base: *const (),
+
+    /// Zero the globals when the instance is dropped
+    zero_on_drop: bool,
+
+    /// Trap instead of returning -1 when a `memory.grow` fails
+    trap_on_grow_failure: bool,
+
+    /// The number of calls to each of the local functions, if enabled
+    coverage_counters: Vec<u64>,
+
+    /// The current depth of the WebAssembly calls, maintained by the
+    /// compiled code when `max_call_depth` is set
+    call_depth: Box<Cell<u64>>,
+
+    /// The allocations of the module heap, checked by the compiled code
+    /// when `shadow_heap` is set
+    shadow_heap: Option<ShadowHeap>,
+
+    /// The memory the pointers returned by each exported function point
+    /// into, when it's not the memory 0
+    result_memories: HashMap<String, usize>,
+
+    /// The hash of the compiled code, see `artifact_hash`
+    artifact_hash: [u8; 32],
+
+    /// Whether the compiled code meters the gas it uses
+    metering: bool,
+
+    /// The gas used and the gas limit of the current metered call
+    gas_used: Box<Cell<u64>>,
+    gas_limit: Box<Cell<u64>>,
+
+    /// The weights of the `CostTable` categories, read by the metered code
+    gas_costs: Box<[u64; COST_CATEGORIES]>,
+
+    /// Whether the calls set the floating point environment of WebAssembly
+    strict_float: bool,
+
+    /// Whether the compiled code reports the function entries and exits
+    profiling: bool,
+
+    /// The execution profile, fed by the compiled code when `profiling` is set
+    profiler: RefCell<Profiler>,
 }
 
 /// Contains pointers to data (heaps, globals, tables) needed
@@ -123,23 +347,594 @@ pub struct InstanceOptions {
     pub mock_missing_globals: bool,
     pub mock_missing_tables: bool,
     pub isa: Box<TargetIsa>,
+    // Shall we zero the memories and globals when the instance is dropped?
+    // Useful when the instance handles secrets, so they don't linger in freed pages.
+    pub zero_on_drop: bool,
+    // How to lay out the compiled functions in memory.
+    pub code_placement: CodePlacement,
+    // Functions to compile without optimizations, e.g. for bisecting a miscompilation.
+    // They are compiled with a vanilla ISA for the same target, so the ISA specific
+    // settings (like the enabled CPU features) are not carried over.
+    pub unoptimized_functions: HashSet<FuncIndex>,
+    // Shall we trap when a signed integer addition, subtraction or multiplication
+    // overflows? This is not spec compliant (WebAssembly arithmetic wraps around),
+    // it's only meant for finding numeric bugs while debugging.
+    pub trap_on_int_overflow: bool,
+    // Caps the maximum number of pages of every memory of this instance, so
+    // instances of the same module can get different memory limits. The generated
+    // code doesn't depend on the memory limits, so it's the same for all of them.
+    pub maximum_memory_pages: Option<u32>,
+    // When set to true (e.g. from another thread), the compilation of the functions
+    // stops and the instantiation fails. Useful for bounding the time spent
+    // compiling untrusted modules.
+    pub cancel_compilation: Option<Arc<AtomicBool>>,
+    // The maximum amount of machine code (in bytes) that the functions may compile to.
+    // Protects against small modules that generate huge amounts of code.
+    pub max_code_bytes: Option<usize>,
+    // Shall a failed `memory.grow` trap instead of returning -1? This is not spec
+    // compliant, it's only meant for finding where a module runs out of memory.
+    pub trap_on_grow_failure: bool,
+    // Shall we count the calls to every local function? The counts are
+    // returned by `Instance::coverage`, e.g. for coverage-guided fuzzing.
+    pub coverage: bool,
+    // The maximum depth of nested WebAssembly calls, trapping with `CallStackExhausted`
+    // when exceeded. Unlike the native stack limit, it doesn't depend on the platform
+    // or on the size of the stack frames, so the recursion limit is deterministic.
+    pub max_call_depth: Option<u64>,
+    // The range of the memory 0 managed by the module allocator. When set, every access
+    // within it must fall in a region registered with `Instance::shadow_allocate`, or it
+    // traps with `InvalidHeapAccess`. Meant for finding heap bugs, as it's really slow.
+    pub shadow_heap: Option<Range<u32>>,
+    // Shall the compiled code meter the gas it uses, with the weights set with
+    // `Instance::set_fuel_costs` (one unit per Cranelift instruction by default)?
+    // Required for `Instance::execute_fn_metered`.
+    pub metering: bool,
+    // Shall the calls run with the floating point environment mandated by
+    // WebAssembly (round-to-nearest-even, no flushing of the denormals)?
+    // The environment of the host is restored after each call.
+    pub strict_float: bool,
+    // The maximum total size in bytes of the instance (see `Instance::total_footprint`)
+    // after instantiation, failing with a `LinkError` when exceeded.
+    pub max_footprint_bytes: Option<usize>,
+    // Shall the compiled code report the entry and exit of every local function?
+    // Required for recording execution profiles with `Instance::profile`.
+    pub profiling: bool,
+}
+
+impl InstanceOptions {
+    /// Returns the default options for compiling with `isa`: spec compliant
+    /// and without any debugging instrumentation or limits. The missing
+    /// imports, globals and tables are mocked if `mock_missing` is set.
+    pub fn new(isa: Box<TargetIsa>, mock_missing: bool) -> Self {
+        InstanceOptions {
+            mock_missing_imports: mock_missing,
+            mock_missing_globals: mock_missing,
+            mock_missing_tables: mock_missing,
+            isa,
+            zero_on_drop: false,
+            code_placement: CodePlacement::PerFunction,
+            unoptimized_functions: HashSet::new(),
+            trap_on_int_overflow: false,
+            maximum_memory_pages: None,
+            cancel_compilation: None,
+            max_code_bytes: None,
+            trap_on_grow_failure: false,
+            coverage: false,
+            max_call_depth: None,
+            shadow_heap: None,
+            metering: false,
+            strict_float: false,
+            max_footprint_bytes: None,
+            profiling: false,
+        }
+    }
+}
+
+/// How the machine code of the compiled functions is allocated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CodePlacement {
+    /// Every function gets its own allocation, so the code of a single
+    /// function can be released without touching the others.
+    PerFunction,
+    /// All the functions are packed in a single allocation, which
+    /// uses fewer pages and keeps the code close together.
+    Packed,
+}
+
+impl Default for CodePlacement {
+    fn default() -> Self {
+        CodePlacement::PerFunction
+    }
 }
 
+/// The alignment of each function in a packed code allocation
+const PACKED_FUNCTION_ALIGN: usize = 16;
+
+/// The number of integer and float arguments that `execute_fn` can pass to
+/// a function. They are passed in the System V x86-64 argument registers,
+/// and one of the integer registers is taken by the vmctx.
+const MAX_INT_ARGS: usize = 5;
+const MAX_FLOAT_ARGS: usize = 8;
+
+// Functions are called with all the argument registers set: the callee
+// ignores the ones that it doesn't use.
+type IntRetFn =
+    extern "C" fn(u64, u64, u64, u64, u64, u64, f64, f64, f64, f64, f64, f64, f64, f64) -> u64;
+type FloatRetFn =
+    extern "C" fn(u64, u64, u64, u64, u64, u64, f64, f64, f64, f64, f64, f64, f64, f64) -> f64;
+
 extern "C" fn mock_fn() -> i32 {
     return 0;
 }
 
+/// Information about the native stack frame of a compiled function
+#[derive(Clone, Copy, Debug)]
+pub struct FrameInfo {
+    /// Total size of the native stack frame, in bytes: the most stack the
+    /// function uses itself, not counting its callees
+    pub max_stack: u32,
+    /// Bytes of the frame used for spilling values (locals and operands)
+    /// that didn't fit in registers
+    pub locals_bytes: u32,
+}
+
+/// An out-parameter of a function: the argument `arg_index` is a pointer
+/// in the memory 0 where the function writes a value of type `ty`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutParam {
+    pub arg_index: usize,
+    pub ty: Type,
+}
+
+/// An exported function with its first argument (usually a context or
+/// `this` pointer) bound to a fixed value, see `Instance::bind_first`.
+pub struct BoundFunction<'a> {
+    instance: &'a Instance,
+    module: &'a Module,
+    name: String,
+    first: Value,
+}
+
+impl<'a> BoundFunction<'a> {
+    /// Calls the function with the bound first argument followed by `args`.
+    pub fn call(&self, args: &[Value]) -> Result<Vec<Value>, ErrorKind> {
+        let mut all_args = Vec::with_capacity(args.len() + 1);
+        all_args.push(self.first);
+        all_args.extend_from_slice(args);
+        self.instance.execute_fn(self.module, &self.name, &all_args)
+    }
+}
+
+const COST_DEFAULT: usize = 0;
+const COST_MEMORY: usize = 1;
+const COST_CALL: usize = 2;
+const COST_CONTROL: usize = 3;
+const COST_CATEGORIES: usize = 4;
+
+/// The gas charged by the metered code for each kind of Cranelift instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CostTable {
+    /// Arithmetic and any other instruction
+    pub default: u64,
+    /// Loads and stores
+    pub memory: u64,
+    /// Direct and indirect calls
+    pub call: u64,
+    /// Branches, jumps, returns and traps
+    pub control: u64,
+}
+
+impl CostTable {
+    fn weights(&self) -> [u64; COST_CATEGORIES] {
+        let mut weights = [0; COST_CATEGORIES];
+        weights[COST_DEFAULT] = self.default;
+        weights[COST_MEMORY] = self.memory;
+        weights[COST_CALL] = self.call;
+        weights[COST_CONTROL] = self.control;
+        weights
+    }
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        CostTable {
+            default: 1,
+            memory: 1,
+            call: 1,
+            control: 1,
+        }
+    }
+}
+
+/// The host memory used by an instance, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FootprintBreakdown {
+    /// The executable allocations of the compiled functions
+    pub code: usize,
+    /// The current size of the linear memories
+    pub memory: usize,
+    pub tables: usize,
+    pub globals: usize,
+}
+
+impl FootprintBreakdown {
+    pub fn total(&self) -> usize {
+        self.code + self.memory + self.tables + self.globals
+    }
+}
+
 struct CompiledFunction {
     code_buf: Vec<u8>,
     reloc_sink: RelocSink,
     trap_sink: binemit::NullTrapSink,
+    compile_time: Duration,
+    frame_info: FrameInfo,
+}
+
+/// Returns the `iadd`, `isub` and `imul` instructions of the function. In a
+/// function just translated, they all come from the `add`, `sub` and `mul`
+/// WebAssembly operators: neither the translator nor the `FuncEnvironment`
+/// emit them for anything else (the address computations are done by
+/// `heap_addr` and `table_addr`, which are only legalized afterwards).
+fn wasm_arithmetic_insts(func: &Function) -> Vec<Inst> {
+    let mut arithmetic_insts: Vec<Inst> = Vec::new();
+    for ebb in func.layout.ebbs() {
+        for inst in func.layout.ebb_insts(ebb) {
+            match func.dfg[inst].opcode() {
+                Opcode::Iadd | Opcode::Isub | Opcode::Imul => {}
+                _ => continue,
+            }
+            let ty = func.dfg.value_type(func.dfg.first_result(inst));
+            if ty == types::I32 || ty == types::I64 {
+                arithmetic_insts.push(inst);
+            }
+        }
+    }
+    arithmetic_insts
+}
+
+/// Inserts a check after each of the given signed `iadd`, `isub` and `imul`
+/// (see `wasm_arithmetic_insts`), trapping with `IntegerOverflow` if the
+/// operation overflowed.
+fn insert_overflow_checks(func: &mut Function, arithmetic_insts: Vec<Inst>) {
+    if arithmetic_insts.is_empty() {
+        return;
+    }
+    let trap_ebb = func.dfg.make_ebb();
+    for inst in arithmetic_insts {
+        let opcode = func.dfg[inst].opcode();
+        let (a, b) = {
+            let args = func.dfg.inst_args(inst);
+            (args[0], args[1])
+        };
+        let result = func.dfg.first_result(inst);
+        let ty = func.dfg.value_type(result);
+
+        // Every instruction is followed at least by the EBB terminator,
+        // so we can insert the checks before the next instruction.
+        let mut pos = FuncCursor::new(func).at_inst(inst);
+        pos.next_inst();
+        let overflowed = match opcode {
+            Opcode::Imul => {
+                // The high half of the product must be the sign extension of the low half
+                let high = pos.ins().smulhi(a, b);
+                let sign = pos.ins().sshr_imm(result, i64::from(ty.bits()) - 1);
+                pos.ins().icmp(IntCC::NotEqual, high, sign)
+            }
+            _ => {
+                // The sign of the result is wrong: adding operands of the same sign, or
+                // subtracting operands of different signs, gave a result of the other sign
+                let operands_sign = if opcode == Opcode::Iadd {
+                    let b_result = pos.ins().bxor(b, result);
+                    let a_result = pos.ins().bxor(a, result);
+                    pos.ins().band(a_result, b_result)
+                } else {
+                    let a_b = pos.ins().bxor(a, b);
+                    let a_result = pos.ins().bxor(a, result);
+                    pos.ins().band(a_b, a_result)
+                };
+                pos.ins().icmp_imm(IntCC::SignedLessThan, operands_sign, 0)
+            }
+        };
+        pos.ins().brnz(overflowed, trap_ebb, &[]);
+    }
+
+    append_unwind_ebb(func, trap_ebb, "integer_overflow");
+}
+
+/// Inserts an increment of the 64-bit counter at `counter_addr` at the
+/// entry of the function.
+fn insert_entry_counter(func: &mut Function, isa: &TargetIsa, counter_addr: usize) {
+    let mut pos = FuncCursor::new(func);
+    pos.next_ebb();
+    // The entry EBB has at least a terminator
+    pos.next_inst();
+    let addr = pos.ins().iconst(isa.pointer_type(), counter_addr as i64);
+    let count = pos.ins().load(types::I64, MemFlags::new(), addr, 0);
+    let count = pos.ins().iadd_imm(count, 1);
+    pos.ins().store(MemFlags::new(), count, addr, 0);
+}
+
+/// Maintains the call depth in the 64-bit counter at `depth_addr`: it's
+/// incremented at the entry of the function, trapping with
+/// `CallStackExhausted` if it goes over `max_depth`, and decremented
+/// before every return.
+fn insert_call_depth_check(
+    func: &mut Function,
+    isa: &TargetIsa,
+    depth_addr: usize,
+    max_depth: u64,
+) {
+    let pointer_type = isa.pointer_type();
+    let trap_ebb = func.dfg.make_ebb();
+    let mut return_insts: Vec<Inst> = Vec::new();
+    {
+        let mut pos = FuncCursor::new(func);
+        while let Some(_ebb) = pos.next_ebb() {
+            while let Some(inst) = pos.next_inst() {
+                if pos.func.dfg[inst].opcode().is_return() {
+                    return_insts.push(inst);
+                }
+            }
+        }
+    }
+
+    {
+        let mut pos = FuncCursor::new(func);
+        pos.next_ebb();
+        // The entry EBB has at least a terminator
+        pos.next_inst();
+        let addr = pos.ins().iconst(pointer_type, depth_addr as i64);
+        let depth = pos.ins().load(types::I64, MemFlags::new(), addr, 0);
+        let depth = pos.ins().iadd_imm(depth, 1);
+        pos.ins().store(MemFlags::new(), depth, addr, 0);
+        let exceeded = pos
+            .ins()
+            .icmp_imm(IntCC::UnsignedGreaterThan, depth, max_depth as i64);
+        pos.ins().brnz(exceeded, trap_ebb, &[]);
+    }
+
+    for inst in return_insts {
+        let mut pos = FuncCursor::new(func).at_inst(inst);
+        let addr = pos.ins().iconst(pointer_type, depth_addr as i64);
+        let depth = pos.ins().load(types::I64, MemFlags::new(), addr, 0);
+        let depth = pos.ins().iadd_imm(depth, -1);
+        pos.ins().store(MemFlags::new(), depth, addr, 0);
+    }
+
+    append_unwind_ebb(func, trap_ebb, "call_stack_exhausted");
+}
+
+/// Appends `trap_ebb` to the function, calling the runtime helper `helper`
+/// (which unwinds to the host, so it never returns).
+fn append_unwind_ebb(func: &mut Function, trap_ebb: Ebb, helper: &str) {
+    let sig_ref = func.import_signature(Signature::new(CallConv::SystemV));
+    let func_ref = func.import_function(ExtFuncData {
+        name: ExternalName::testcase(helper),
+        signature: sig_ref,
+        colocated: false,
+    });
+    func.layout.append_ebb(trap_ebb);
+    let mut pos = FuncCursor::new(func).at_bottom(trap_ebb);
+    pos.ins().call(func_ref, &[]);
+    pos.ins().trap(TrapCode::UnreachableCodeReached);
+}
+
+/// Returns the `CostTable` category of the instructions with `opcode`.
+fn cost_category(opcode: Opcode) -> usize {
+    if opcode.can_load() || opcode.can_store() {
+        COST_MEMORY
+    } else if opcode.is_call() {
+        COST_CALL
+    } else if opcode.is_branch() || opcode.is_terminator() {
+        COST_CONTROL
+    } else {
+        COST_DEFAULT
+    }
+}
+
+/// Charges the gas of every instruction: the gas used, in the 64-bit counter
+/// at `used_addr`, is increased when entering an EBB by the number of its
+/// instructions of each category times the weight of the category (read from
+/// the weights at `costs_addr`, so they can be changed after compilation),
+/// trapping with `OutOfGas` if it goes over the limit at `limit_addr`.
+fn insert_gas_metering(
+    func: &mut Function,
+    isa: &TargetIsa,
+    (used_addr, limit_addr, costs_addr): (usize, usize, usize),
+) {
+    let pointer_type = isa.pointer_type();
+    let trap_ebb = func.dfg.make_ebb();
+    let mut ebbs: Vec<(Inst, [i64; COST_CATEGORIES])> = Vec::new();
+    {
+        let mut pos = FuncCursor::new(func);
+        while let Some(_ebb) = pos.next_ebb() {
+            let mut first_inst = None;
+            let mut counts = [0; COST_CATEGORIES];
+            while let Some(inst) = pos.next_inst() {
+                first_inst = first_inst.or(Some(inst));
+                counts[cost_category(pos.func.dfg[inst].opcode())] += 1;
+            }
+            if let Some(inst) = first_inst {
+                ebbs.push((inst, counts));
+            }
+        }
+    }
+
+    for (inst, counts) in ebbs {
+        let mut pos = FuncCursor::new(func).at_inst(inst);
+        let addr = pos.ins().iconst(pointer_type, used_addr as i64);
+        let costs = pos.ins().iconst(pointer_type, costs_addr as i64);
+        let mut used = pos.ins().load(types::I64, MemFlags::new(), addr, 0);
+        for (category, count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            let offset = (category * size_of::<u64>()) as i32;
+            let weight = pos.ins().load(types::I64, MemFlags::new(), costs, offset);
+            let cost = pos.ins().imul_imm(weight, *count);
+            used = pos.ins().iadd(used, cost);
+        }
+        pos.ins().store(MemFlags::new(), used, addr, 0);
+        let addr = pos.ins().iconst(pointer_type, limit_addr as i64);
+        let limit = pos.ins().load(types::I64, MemFlags::new(), addr, 0);
+        let exceeded = pos.ins().icmp(IntCC::UnsignedGreaterThan, used, limit);
+        pos.ins().brnz(exceeded, trap_ebb, &[]);
+    }
+
+    append_unwind_ebb(func, trap_ebb, "out_of_gas");
+}
+
+/// Returns the WebAssembly address, offset and size of the access of a load
+/// or store to the linear memory, or `None` for any other instruction.
+fn heap_access(func: &Function, inst: Inst) -> Option<(IrValue, i32, u32)> {
+    let (addr, offset, ty) = match func.dfg[inst] {
+        InstructionData::Load { arg, offset, .. } => {
+            (arg, offset, func.dfg.value_type(func.dfg.first_result(inst)))
+        }
+        InstructionData::Store { args, offset, .. } => {
+            (args[1], offset, func.dfg.value_type(args[0]))
+        }
+        _ => return None,
+    };
+    let size = match func.dfg[inst].opcode() {
+        Opcode::Uload8 | Opcode::Sload8 | Opcode::Istore8 => 1,
+        Opcode::Uload16 | Opcode::Sload16 | Opcode::Istore16 => 2,
+        Opcode::Uload32 | Opcode::Sload32 | Opcode::Istore32 => 4,
+        Opcode::Load | Opcode::Store => ty.bytes(),
+        _ => return None,
+    };
+    // Only the accesses through a `heap_addr` are to the linear memory
+    match func.dfg.value_def(addr) {
+        ValueDef::Result(addr_inst, _) if func.dfg[addr_inst].opcode() == Opcode::HeapAddr => {
+            Some((func.dfg.inst_args(addr_inst)[0], offset.into(), size))
+        }
+        _ => None,
+    }
+}
+
+/// Inserts a call to the `shadow_check` runtime helper before every load
+/// and store to the linear memory, which traps if the access isn't allowed
+/// by the shadow heap of the instance.
+fn insert_shadow_checks(func: &mut Function, isa: &TargetIsa) {
+    let mut accesses: Vec<(Inst, IrValue, i32, u32)> = Vec::new();
+    {
+        let mut pos = FuncCursor::new(func);
+        while let Some(_ebb) = pos.next_ebb() {
+            while let Some(inst) = pos.next_inst() {
+                if let Some((addr, offset, size)) = heap_access(pos.func, inst) {
+                    accesses.push((inst, addr, offset, size));
+                }
+            }
+        }
+    }
+    if accesses.is_empty() {
+        return;
+    }
+
+    let mut signature = Signature::new(CallConv::SystemV);
+    signature.params.push(AbiParam::new(types::I32));
+    signature.params.push(AbiParam::new(types::I32));
+    signature.params.push(AbiParam::new(types::I32));
+    signature
+        .params
+        .push(AbiParam::special(isa.pointer_type(), ArgumentPurpose::VMContext));
+    let sig_ref = func.import_signature(signature);
+    let func_ref = func.import_function(ExtFuncData {
+        name: ExternalName::testcase("shadow_check"),
+        signature: sig_ref,
+        colocated: false,
+    });
+    let vmctx = func.special_param(ArgumentPurpose::VMContext).unwrap();
+
+    for (inst, addr, offset, size) in accesses {
+        let mut pos = FuncCursor::new(func).at_inst(inst);
+        let offset = pos.ins().iconst(types::I32, i64::from(offset));
+        let size = pos.ins().iconst(types::I32, i64::from(size));
+        pos.ins().call(func_ref, &[addr, offset, size, vmctx]);
+    }
+}
+
+/// Inserts a call to the `profile_enter` runtime helper at the entry of the
+/// function, and a call to `profile_exit` before each of its returns.
+fn insert_profile_hooks(func: &mut Function, isa: &TargetIsa, func_index: FuncIndex) {
+    let vmctx_param = AbiParam::special(isa.pointer_type(), ArgumentPurpose::VMContext);
+    let mut enter_signature = Signature::new(CallConv::SystemV);
+    enter_signature.params.push(AbiParam::new(types::I32));
+    enter_signature.params.push(vmctx_param);
+    let mut exit_signature = Signature::new(CallConv::SystemV);
+    exit_signature.params.push(vmctx_param);
+    let enter_sig_ref = func.import_signature(enter_signature);
+    let exit_sig_ref = func.import_signature(exit_signature);
+    let enter_ref = func.import_function(ExtFuncData {
+        name: ExternalName::testcase("profile_enter"),
+        signature: enter_sig_ref,
+        colocated: false,
+    });
+    let exit_ref = func.import_function(ExtFuncData {
+        name: ExternalName::testcase("profile_exit"),
+        signature: exit_sig_ref,
+        colocated: false,
+    });
+    let vmctx = func.special_param(ArgumentPurpose::VMContext).unwrap();
+
+    let mut returns: Vec<Inst> = Vec::new();
+    {
+        let mut pos = FuncCursor::new(func);
+        while let Some(_ebb) = pos.next_ebb() {
+            while let Some(inst) = pos.next_inst() {
+                if pos.func.dfg[inst].opcode().is_return() {
+                    returns.push(inst);
+                }
+            }
+        }
+    }
+
+    let mut pos = FuncCursor::new(func);
+    pos.next_ebb();
+    // The entry EBB has at least a terminator
+    pos.next_inst();
+    let index = pos.ins().iconst(types::I32, func_index.index() as i64);
+    pos.ins().call(enter_ref, &[index, vmctx]);
+    for inst in returns {
+        let mut pos = FuncCursor::new(func).at_inst(inst);
+        pos.ins().call(exit_ref, &[vmctx]);
+    }
 }
 
 fn compile_function(
     isa: &TargetIsa,
     function_body: &Function,
+    trap_on_int_overflow: bool,
+    coverage_counter: Option<usize>,
+    call_depth_limit: Option<(usize, u64)>,
+    shadow_checks: bool,
+    gas_counters: Option<(usize, usize, usize)>,
+    profiled_func: Option<FuncIndex>,
 ) -> Result<CompiledFunction, ErrorKind> {
+    let start = Instant::now();
     let mut func_context = Context::for_function(function_body.to_owned());
+    // The arithmetic to check is picked before any instrumentation is added
+    let arithmetic_insts = if trap_on_int_overflow {
+        wasm_arithmetic_insts(&func_context.func)
+    } else {
+        Vec::new()
+    };
+    // The gas is metered first, so the debugging instrumentation below
+    // doesn't change the cost of the functions
+    if let Some(gas_counters) = gas_counters {
+        insert_gas_metering(&mut func_context.func, isa, gas_counters);
+    }
+    insert_overflow_checks(&mut func_context.func, arithmetic_insts);
+    if let Some(counter_addr) = coverage_counter {
+        insert_entry_counter(&mut func_context.func, isa, counter_addr);
+    }
+    if let Some((depth_addr, max_depth)) = call_depth_limit {
+        insert_call_depth_check(&mut func_context.func, isa, depth_addr, max_depth);
+    }
+    if shadow_checks {
+        insert_shadow_checks(&mut func_context.func, isa);
+    }
+    if let Some(func_index) = profiled_func {
+        insert_profile_hooks(&mut func_context.func, isa, func_index);
+    }
 
     let mut code_buf: Vec<u8> = Vec::new();
     let mut reloc_sink = RelocSink::new();
@@ -152,10 +947,22 @@ fn compile_function(
             ErrorKind::CompileError(e.to_string())
         })?;
 
+    let stack_slots = &func_context.func.stack_slots;
+    let frame_info = FrameInfo {
+        max_stack: stack_slots.frame_size.unwrap_or(0),
+        locals_bytes: stack_slots
+            .iter()
+            .filter(|(_, slot)| slot.kind == StackSlotKind::SpillSlot)
+            .map(|(_, slot)| slot.size)
+            .sum(),
+    };
+
     Ok(CompiledFunction {
         code_buf,
         reloc_sink,
         trap_sink,
+        compile_time: start.elapsed(),
+        frame_info,
     })
 }
 
@@ -173,11 +980,35 @@ impl Instance {
         options: InstanceOptions,
     ) -> Result<Instance, ErrorKind> {
         let mut tables: Vec<Vec<usize>> = Vec::new();
+        let mut element_segments: Vec<Vec<usize>> = Vec::new();
         let mut memories: Vec<LinearMemory> = Vec::new();
+        let mut data_segments: Vec<Vec<u8>> = Vec::new();
+        let mut initialized_ranges: Vec<Vec<Range<usize>>> = Vec::new();
         let mut globals: Vec<u8> = Vec::new();
 
-        let mut functions: Vec<Vec<u8>> = Vec::new();
-        let mut import_functions: Vec<*const u8> = Vec::new();
+        let mut code_buffers: Vec<Vec<u8>> = Vec::new();
+        let mut functions: Vec<*const u8> = Vec::new();
+        let import_functions: Vec<*const u8>;
+        let mut import_relocations: Vec<(FuncIndex, Relocation)> = Vec::new();
+        let mut code_sizes: Vec<usize> = Vec::new();
+        let mut compile_times: Vec<Duration> = Vec::new();
+        let mut frame_infos: Vec<FrameInfo> = Vec::new();
+        let mut coverage_counters: Vec<u64> = Vec::new();
+        let mut artifact_hash = [0u8; 32];
+        let call_depth: Box<Cell<u64>> = Box::new(Cell::new(0));
+        let gas_used: Box<Cell<u64>> = Box::new(Cell::new(0));
+        let gas_limit: Box<Cell<u64>> = Box::new(Cell::new(u64::max_value()));
+        let gas_costs: Box<[u64; COST_CATEGORIES]> = Box::new(CostTable::default().weights());
+
+        if let Some(ref triple) = module.info.triple {
+            if triple.architecture != options.isa.triple().architecture {
+                return Err(ErrorKind::LinkError(format!(
+                    "The module was compiled for {}, but the instance targets {}",
+                    triple,
+                    options.isa.triple()
+                )));
+            }
+        }
 
         debug!("Instance - Instantiating functions");
         // Instantiate functions
@@ -185,66 +1016,174 @@ impl Instance {
             functions.reserve_exact(module.info.functions.len());
             let mut relocations = Vec::new();
 
-            // let imported_functions: Vec<String> = module.info.imported_funcs.iter().map(|(module, field)| {
-            //     format!(" * {}.{}", module, field)
-            // }).collect();
-
-            // println!("Instance imported functions: \n{}", imported_functions.join("\n"));
+            trace!(
+                "Instance - Imported functions: {:?}",
+                module.info.imported_funcs
+            );
 
             // We walk through the imported functions and set the relocations
             // for each of this functions to be an empty vector (as is defined outside of wasm)
-            for (module, field) in module.info.imported_funcs.iter() {
-                let imported = import_object.get(&module.as_str(), &field.as_str());
-                let function: &*const u8 = match imported {
-                    Some(ImportValue::Func(f)) => f,
-                    None => {
-                        if options.mock_missing_imports {
-                            debug!(
-                                "The import {}.{} is not provided, therefore will be mocked.",
-                                module, field
-                            );
-                            &(mock_fn as *const u8)
-                        } else {
-                            return Err(ErrorKind::LinkError(format!(
-                                "Imported function {}.{} was not provided in the import_functions",
-                                module, field
-                            )));
-                        }
-                    }
-                    other => panic!("Expected function import, received {:?}", other),
-                };
-                // println!("GET FUNC {:?}", function);
-                import_functions.push(*function);
+            import_functions =
+                resolve_import_functions(module, &import_object, options.mock_missing_imports)?;
+            for _ in 0..import_functions.len() {
                 relocations.push(vec![]);
             }
 
+            let unoptimized_isa = if options.unoptimized_functions.is_empty() {
+                None
+            } else {
+                let mut builder = settings::builder();
+                builder.set("opt_level", "fastest").unwrap();
+                let isa = isa::lookup(options.isa.triple().clone())
+                    .map_err(|e| ErrorKind::CompileError(format!("{:?}", e)))?
+                    .finish(settings::Flags::new(builder));
+                Some(isa)
+            };
+
             debug!("Instance - Compiling functions");
             // Compile the functions (from cranelift IR to machine code)
             let values: Vec<&Function> = Vec::from_iter(module.info.function_bodies.values());
             // let isa: &TargetIsa = &*options.isa;
+            let code_bytes = AtomicUsize::new(0);
+            // The counters are incremented through their absolute address, which
+            // stays the same when the vector is moved into the instance
+            if options.coverage {
+                coverage_counters = vec![0; values.len()];
+            }
+            let coverage_base = if options.coverage {
+                Some(coverage_counters.as_ptr() as usize)
+            } else {
+                None
+            };
+            let call_depth_limit = options
+                .max_call_depth
+                .map(|max_depth| (call_depth.as_ptr() as usize, max_depth));
+            let gas_counters = if options.metering {
+                Some((
+                    gas_used.as_ptr() as usize,
+                    gas_limit.as_ptr() as usize,
+                    gas_costs.as_ptr() as usize,
+                ))
+            } else {
+                None
+            };
+            let code_budget_exceeded = |code_bytes: usize| match options.max_code_bytes {
+                Some(max_code_bytes) if code_bytes > max_code_bytes => {
+                    Err(ErrorKind::CodeBudgetExceeded(max_code_bytes))
+                }
+                _ => Ok(()),
+            };
             let compiled_funcs: Vec<CompiledFunction> = values
                 .par_iter()
-                .map(|function_body| -> CompiledFunction {
-                    // let r = *Arc::from_raw(isa_ptr);
-                    compile_function(&*options.isa, function_body).unwrap()
-                    // unimplemented!()
+                .enumerate()
+                .map(|(i, function_body)| -> Result<CompiledFunction, ErrorKind> {
+                    if let Some(ref cancel) = options.cancel_compilation {
+                        if cancel.load(Ordering::Relaxed) {
+                            return Err(ErrorKind::CompilationCancelled);
+                        }
+                    }
+                    let func_index = module.func_index(DefinedFuncIndex::new(i));
+                    let isa: &TargetIsa = match unoptimized_isa {
+                        Some(ref isa) if options.unoptimized_functions.contains(&func_index) => {
+                            &**isa
+                        }
+                        _ => &*options.isa,
+                    };
+                    // Stop early if other functions already went over the budget
+                    code_budget_exceeded(code_bytes.load(Ordering::Relaxed))?;
+                    let coverage_counter = coverage_base.map(|base| base + i * size_of::<u64>());
+                    let compiled = compile_function(
+                        isa,
+                        function_body,
+                        options.trap_on_int_overflow,
+                        coverage_counter,
+                        call_depth_limit,
+                        options.shadow_heap.is_some(),
+                        gas_counters,
+                        if options.profiling {
+                            Some(func_index)
+                        } else {
+                            None
+                        },
+                    )?;
+                    let size = compiled.code_buf.len();
+                    code_budget_exceeded(code_bytes.fetch_add(size, Ordering::Relaxed) + size)?;
+                    Ok(compiled)
                 })
-                .collect();
-
+                .collect::<Result<Vec<CompiledFunction>, ErrorKind>>()?;
+
+            let mut code_offsets: Vec<usize> = Vec::new();
+            let mut packed_code: Vec<u8> = Vec::new();
+            // The functions are hashed before being relocated, so the hash
+            // doesn't depend on where the code and its callees are loaded.
+            // The addresses of the instance counters embedded by the
+            // instrumentation are replaced by their index in `counter_ranges`.
+            let mut counter_ranges: Vec<Range<u64>> = vec![
+                call_depth.as_ptr() as u64..call_depth.as_ptr() as u64 + 8,
+                gas_used.as_ptr() as u64..gas_used.as_ptr() as u64 + 8,
+                gas_limit.as_ptr() as u64..gas_limit.as_ptr() as u64 + 8,
+                gas_costs.as_ptr() as u64
+                    ..gas_costs.as_ptr() as u64 + (COST_CATEGORIES * size_of::<u64>()) as u64,
+            ];
+            if let Some(base) = coverage_base {
+                let len = (coverage_counters.len() * size_of::<u64>()) as u64;
+                counter_ranges.push(base as u64..base as u64 + len);
+            }
+            let mut hasher = Sha256::new();
+            hasher.input(options.isa.triple().to_string().as_bytes());
             for compiled_func in compiled_funcs.into_iter() {
                 let CompiledFunction {
                     code_buf,
                     reloc_sink,
+                    compile_time,
+                    frame_info,
                     ..
                 } = compiled_func;
 
-                // let func_offset = code_buf;
-                protect_codebuf(&code_buf).unwrap();
-                functions.push(code_buf);
+                let length = format!("{}:", code_buf.len());
+                hasher.input(length.as_bytes());
+                hasher.input(&normalize_addresses(&code_buf, &counter_ranges));
+                for reloc in &reloc_sink.func_relocs {
+                    let description = format!(
+                        "{:?} {} {} {:?};",
+                        reloc.reloc, reloc.offset, reloc.addend, reloc.target
+                    );
+                    hasher.input(description.as_bytes());
+                }
+
+                code_sizes.push(code_buf.len());
+                compile_times.push(compile_time);
+                frame_infos.push(frame_info);
+
+                match options.code_placement {
+                    CodePlacement::PerFunction => {
+                        protect_codebuf(&code_buf).unwrap();
+                        functions.push(code_buf.as_ptr());
+                        code_buffers.push(code_buf);
+                    }
+                    CodePlacement::Packed => {
+                        let padding = (PACKED_FUNCTION_ALIGN
+                            - packed_code.len() % PACKED_FUNCTION_ALIGN)
+                            % PACKED_FUNCTION_ALIGN;
+                        packed_code.resize(packed_code.len() + padding, 0);
+                        code_offsets.push(packed_code.len());
+                        packed_code.extend_from_slice(&code_buf);
+                    }
+                }
 
                 // context_and_offsets.push(func_context);
                 relocations.push(reloc_sink.func_relocs);
             }
+            artifact_hash.copy_from_slice(&hasher.result());
+
+            if options.code_placement == CodePlacement::Packed && !packed_code.is_empty() {
+                // The code buffer is not resized anymore, so the
+                // function addresses stay valid
+                protect_codebuf(&packed_code).unwrap();
+                let base = packed_code.as_ptr();
+                functions.extend(code_offsets.iter().map(|offset| unsafe { base.add(*offset) }));
+                code_buffers.push(packed_code);
+            }
 
             // compiled_funcs?;
 
@@ -257,6 +1196,9 @@ impl Instance {
                 for ref reloc in function_relocs {
                     let target_func_address: isize = match reloc.target {
                         RelocationType::Normal(func_index) => {
+                            if (func_index as usize) < import_functions.len() {
+                                import_relocations.push((FuncIndex::new(i), (*reloc).clone()));
+                            }
                             get_function_addr(&FuncIndex::new(func_index as usize), &import_functions, &functions) as isize
                         },
                         RelocationType::CurrentMemory => {
@@ -265,6 +1207,24 @@ impl Instance {
                         RelocationType::GrowMemory => {
                             grow_memory as isize
                         },
+                        RelocationType::CallStackExhausted => {
+                            call_stack_exhausted as isize
+                        },
+                        RelocationType::IntegerOverflow => {
+                            integer_overflow as isize
+                        },
+                        RelocationType::ProfileEnter => {
+                            profile_enter as isize
+                        },
+                        RelocationType::ProfileExit => {
+                            profile_exit as isize
+                        },
+                        RelocationType::ShadowCheck => {
+                            shadow_check as isize
+                        },
+                        RelocationType::OutOfGas => {
+                            out_of_gas as isize
+                        },
                         RelocationType::LibCall(LibCall::CeilF32) => {
                             math_intrinsics::ceilf32 as isize
                         },
@@ -297,23 +1257,7 @@ impl Instance {
 
                     let func_addr =
                         get_function_addr(&FuncIndex::new(i), &import_functions, &functions);
-                    match reloc.reloc {
-                        Reloc::Abs8 => unsafe {
-                            let reloc_address = func_addr.offset(reloc.offset as isize) as i64;
-                            let reloc_addend = reloc.addend;
-                            let reloc_abs = target_func_address as i64 + reloc_addend;
-                            write_unaligned(reloc_address as *mut i64, reloc_abs);
-                        },
-                        Reloc::X86PCRel4 => unsafe {
-                            let reloc_address = func_addr.offset(reloc.offset as isize) as isize;
-                            let reloc_addend = reloc.addend as isize;
-                            // TODO: Handle overflow.
-                            let reloc_delta_i32 =
-                                (target_func_address - reloc_address + reloc_addend) as i32;
-                            write_unaligned(reloc_address as *mut i32, reloc_delta_i32);
-                        },
-                        _ => panic!("unsupported reloc kind"),
-                    }
+                    apply_relocation(func_addr, reloc, target_func_address);
                 }
             }
         }
@@ -342,7 +1286,15 @@ impl Instance {
                     GlobalInit::I64Const(n) => n,
                     GlobalInit::F32Const(f) => f as _, // unsafe { mem::transmute(f as f64) },
                     GlobalInit::F64Const(f) => f as _, // unsafe { mem::transmute(f) },
-                    GlobalInit::GlobalRef(global_index) => globals_data[global_index.index()],
+                    GlobalInit::GlobalRef(global_index) => {
+                        check_initializer_global(
+                            module,
+                            global_index,
+                            i,
+                            &format!("The initializer of the global {}", i),
+                        )?;
+                        globals_data[global_index.index()]
+                    }
                     GlobalInit::Import() => {
                         let (module_name, field_name) = import_name
                             .as_ref()
@@ -417,9 +1369,17 @@ impl Instance {
             }
 
             // instantiate tables
-            for table_element in &module.info.table_elements {
+            for (i, table_element) in module.info.table_elements.iter().enumerate() {
                 let base = match table_element.base {
-                    Some(global_index) => globals_data[global_index.index()] as usize,
+                    Some(global_index) => {
+                        check_initializer_global(
+                            module,
+                            global_index,
+                            globals_data.len(),
+                            &format!("The offset of the element segment {}", i),
+                        )?;
+                        globals_data[global_index.index()] as usize
+                    }
                     None => 0,
                 };
 
@@ -435,6 +1395,20 @@ impl Instance {
                     table[base + table_element.offset + i] = func_addr as _;
                 }
             }
+
+            // We keep the element segments around (with the functions
+            // already resolved to their addresses) for `table_init`
+            element_segments.reserve_exact(module.info.table_elements.len());
+            for table_element in &module.info.table_elements {
+                let segment: Vec<usize> = table_element
+                    .elements
+                    .iter()
+                    .map(|func_index| {
+                        get_function_addr(func_index, &import_functions, &functions) as usize
+                    })
+                    .collect();
+                element_segments.push(segment);
+            }
         }
 
         debug!("Instance - Instantiating memories");
@@ -446,14 +1420,39 @@ impl Instance {
             // Get memories in module
             for memory in &module.info.memories {
                 let memory = memory.entity;
-                let v =
-                    LinearMemory::new(memory.pages_count as u32, memory.maximum.map(|m| m as u32));
+                let initial = memory.pages_count as u32;
+                let maximum = match (memory.maximum.map(|m| m as u32), options.maximum_memory_pages) {
+                    (Some(maximum), Some(cap)) => Some(maximum.min(cap)),
+                    (maximum, cap) => maximum.or(cap),
+                };
+                if let Some(maximum) = maximum {
+                    if initial > maximum {
+                        return Err(ErrorKind::LinkError(format!(
+                            "Memory needs {} pages, but the instance is limited to {}",
+                            initial, maximum
+                        )));
+                    }
+                }
+                let mut v = LinearMemory::new(initial, maximum);
+                v.set_zero_on_drop(options.zero_on_drop);
                 memories.push(v);
             }
 
-            for init in &module.info.data_initializers {
-                debug_assert!(init.base.is_none(), "globalvar base not supported yet");
-                let offset = init.offset;
+            initialized_ranges.resize(memories.len(), Vec::new());
+            for (i, init) in module.info.data_initializers.iter().enumerate() {
+                let base = match init.base {
+                    Some(global_index) => {
+                        check_initializer_global(
+                            module,
+                            global_index,
+                            globals_data.len(),
+                            &format!("The offset of the data segment {}", i),
+                        )?;
+                        globals_data[global_index.index()] as u32 as usize
+                    }
+                    None => 0,
+                };
+                let offset = base + init.offset;
                 let mem = &mut memories[init.memory_index.index()];
                 let end_of_init = offset + init.data.len();
                 if end_of_init > mem.current_size() {
@@ -463,7 +1462,22 @@ impl Instance {
                 }
                 let to_init = &mut mem[offset..offset + init.data.len()];
                 to_init.copy_from_slice(&init.data);
+                if !init.data.is_empty() {
+                    initialized_ranges[init.memory_index.index()].push(offset..end_of_init);
+                }
+            }
+            for ranges in &mut initialized_ranges {
+                merge_ranges(ranges);
             }
+
+            // We keep the data segments around for `memory_init`
+            data_segments.extend(
+                module
+                    .info
+                    .data_initializers
+                    .iter()
+                    .map(|init| init.data.clone()),
+            );
         }
 
         let start_func: Option<FuncIndex> =
@@ -492,15 +1506,71 @@ impl Instance {
 
         // let mem = data_pointers.memories;
 
-        Ok(Instance {
+        let instance = Instance {
             data_pointers,
             tables: Arc::new(tables.into_iter().collect()), // tables.into_iter().map(|table| RwLock::new(table)).collect()),
+            element_segments,
             memories: Arc::new(memories.into_iter().collect()),
+            data_segments,
+            initialized_ranges,
             globals,
+            code_buffers,
             functions,
             import_functions,
+            import_relocations,
+            code_sizes,
+            compile_times,
+            frame_infos,
+            tables_pointer,
+            memories_pointer,
             start_func,
-        })
+            zero_on_drop: options.zero_on_drop,
+            trap_on_grow_failure: options.trap_on_grow_failure,
+            coverage_counters,
+            call_depth,
+            shadow_heap: options.shadow_heap.map(ShadowHeap::new),
+            result_memories: HashMap::new(),
+            artifact_hash,
+            metering: options.metering,
+            strict_float: options.strict_float,
+            profiling: options.profiling,
+            profiler: RefCell::new(Profiler::default()),
+            gas_used,
+            gas_limit,
+            gas_costs,
+        };
+
+        if let Some(max_footprint_bytes) = options.max_footprint_bytes {
+            let footprint = instance.total_footprint();
+            if footprint.total() > max_footprint_bytes {
+                return Err(ErrorKind::LinkError(format!(
+                    "the instance uses {} bytes, exceeding the budget of {} bytes ({:?})",
+                    footprint.total(),
+                    max_footprint_bytes,
+                    footprint
+                )));
+            }
+        }
+        Ok(instance)
+    }
+
+    /// Returns the host memory used by the compiled code, the linear
+    /// memories, the tables and the globals of the instance.
+    pub fn total_footprint(&self) -> FootprintBreakdown {
+        FootprintBreakdown {
+            code: self.code_buffers.iter().map(|code_buf| code_buf.len()).sum(),
+            memory: self
+                .memories
+                .iter()
+                .map(|memory| memory.current_size())
+                .sum(),
+            tables: self
+                .tables
+                .iter()
+                .map(|table| table.len() * size_of::<usize>())
+                .sum(),
+            globals: self.globals.len(),
+        }
     }
 
     pub fn memory_mut(&mut self, memory_index: usize) -> &mut LinearMemory {
@@ -512,40 +1582,1060 @@ impl Instance {
             .unwrap_or_else(|| panic!("no memory for index {}", memory_index))
     }
 
-    pub fn memories(&self) -> Arc<Vec<LinearMemory>> {
-        self.memories.clone()
+    /// Returns the elements `start..start + len` of the table `table_index`,
+    /// trapping if the range is out of bounds.
+    fn table_range_mut(
+        &mut self,
+        table_index: usize,
+        start: usize,
+        len: usize,
+    ) -> Result<&mut [usize], ErrorKind> {
+        let table = self
+            .tables_mut()
+            .get_mut(table_index)
+            .ok_or_else(|| ErrorKind::RuntimeError(format!("no table for index {}", table_index)))?;
+        match start.checked_add(len) {
+            Some(end) if end <= table.len() => Ok(&mut table[start..end]),
+            _ => Err(ErrorKind::RuntimeError(
+                "out of bounds table access".to_string(),
+            )),
+        }
     }
 
-    pub fn get_function_pointer(&self, func_index: FuncIndex) -> *const u8 {
-        get_function_addr(&func_index, &self.import_functions, &self.functions)
+    /// Copies `len` elements starting at `src` in the table `src_table` into the
+    /// table `dst_table` starting at `dst`. The ranges may overlap.
+    pub fn table_copy(
+        &mut self,
+        dst_table: usize,
+        src_table: usize,
+        dst: usize,
+        src: usize,
+        len: usize,
+    ) -> Result<(), ErrorKind> {
+        let elements: Vec<usize> = self.table_range_mut(src_table, src, len)?.to_vec();
+        self.table_range_mut(dst_table, dst, len)?
+            .copy_from_slice(&elements);
+        Ok(())
     }
 
-    pub fn start(&self) -> Result<(), ErrorKind> {
-        if let Some(func_index) = self.start_func {
-            let func: fn(&Instance) = get_instance_function!(&self, func_index);
-            call_protected!(func(self))
-        } else {
-            Ok(())
-        }
+    /// Copies `len` elements starting at `src` in the element segment
+    /// `segment_index` into the table `table_index` starting at `dst`.
+    pub fn table_init(
+        &mut self,
+        table_index: usize,
+        segment_index: usize,
+        dst: usize,
+        src: usize,
+        len: usize,
+    ) -> Result<(), ErrorKind> {
+        let elements: Vec<usize> = {
+            let segment = self.element_segments.get(segment_index).ok_or_else(|| {
+                ErrorKind::RuntimeError(format!("no element segment for index {}", segment_index))
+            })?;
+            match src.checked_add(len) {
+                Some(end) if end <= segment.len() => segment[src..end].to_vec(),
+                _ => {
+                    return Err(ErrorKind::RuntimeError(
+                        "out of bounds table access".to_string(),
+                    ))
+                }
+            }
+        };
+        self.table_range_mut(table_index, dst, len)?
+            .copy_from_slice(&elements);
+        Ok(())
     }
 
-    /// Returns a slice of the contents of allocated linear memory.
-    pub fn inspect_memory(&self, memory_index: usize, address: usize, len: usize) -> &[u8] {
-        &self
-            .memories
-            .get(memory_index)
-            .unwrap_or_else(|| panic!("no memory for index {}", memory_index))
-            .as_ref()[address..address + len]
+    /// Frees the element segment `segment_index`. Once dropped, the segment
+    /// behaves as an empty one (so only empty ranges of it can be used by `table_init`).
+    pub fn drop_element_segment(&mut self, segment_index: usize) -> Result<(), ErrorKind> {
+        let segment = self.element_segments.get_mut(segment_index).ok_or_else(|| {
+            ErrorKind::RuntimeError(format!("no element segment for index {}", segment_index))
+        })?;
+        *segment = Vec::new();
+        Ok(())
     }
 
-    pub fn memory_offset_addr(&self, index: usize, offset: usize) -> *const usize {
-        let memories: &[LinearMemory] = &self.memories[..];
-        let mem = &memories[index];
-        unsafe { mem[..].as_ptr().add(offset) as *const usize }
+    /// Sets `len` elements starting at `start` in the table `table_index` to `value`.
+    pub fn table_fill(
+        &mut self,
+        table_index: usize,
+        start: usize,
+        value: usize,
+        len: usize,
+    ) -> Result<(), ErrorKind> {
+        for element in self.table_range_mut(table_index, start, len)? {
+            *element = value;
+        }
+        Ok(())
     }
 
-    // Shows the value of a global variable.
-    // pub fn inspect_global(&self, global_index: GlobalIndex, ty: ir::Type) -> &[u8] {
+    /// Grows the memory `memory_index` by `add_pages` pages, returning
+    /// the previous number of pages (or `None` if it couldn't be grown).
+    /// The memory bound seen by the compiled code gets updated accordingly.
+    pub fn grow_memory(&mut self, memory_index: usize, add_pages: u32) -> Option<i32> {
+        let old_pages = self.memory_mut(memory_index).grow(add_pages)?;
+        self.memories_pointer[memory_index].len = self.memories[memory_index].current_size();
+        Some(old_pages)
+    }
+
+    /// Copies `len` bytes from `src` in the memory `src_memory_index` into `dst`
+    /// in the memory `dst_memory_index`. The ranges may only overlap when
+    /// both memory indexes are the same.
+    pub fn memory_copy(
+        &mut self,
+        dst_memory_index: usize,
+        dst: usize,
+        src_memory_index: usize,
+        src: usize,
+        len: usize,
+    ) -> Result<(), ErrorKind> {
+        let memories_len = self.memories.len();
+        for memory_index in &[dst_memory_index, src_memory_index] {
+            if *memory_index >= memories_len {
+                return Err(ErrorKind::RuntimeError(format!(
+                    "no memory for index {}",
+                    memory_index
+                )));
+            }
+        }
+        if dst_memory_index == src_memory_index {
+            return self.memory_mut(dst_memory_index).copy_within(dst, src, len);
+        }
+        let memories = Arc::get_mut(&mut self.memories).unwrap_or_else(|| {
+            panic!("Can't get memories as a mutable pointer (there might exist more mutable pointers to the memories)")
+        });
+        // Split the memories so we can borrow both of them at the same time
+        let (dst_memory, src_memory) = if dst_memory_index < src_memory_index {
+            let (left, right) = memories.split_at_mut(src_memory_index);
+            (&mut left[dst_memory_index], &right[0])
+        } else {
+            let (left, right) = memories.split_at_mut(dst_memory_index);
+            (&mut right[0], &left[src_memory_index])
+        };
+        dst_memory.copy_from(dst, src_memory, src, len)
+    }
+
+    /// Copies `len` bytes starting at `src` in the data segment `segment_index`
+    /// into the memory `memory_index` starting at `dst`.
+    pub fn memory_init(
+        &mut self,
+        memory_index: usize,
+        segment_index: usize,
+        dst: usize,
+        src: usize,
+        len: usize,
+    ) -> Result<(), ErrorKind> {
+        if memory_index >= self.memories.len() {
+            return Err(ErrorKind::RuntimeError(format!(
+                "no memory for index {}",
+                memory_index
+            )));
+        }
+        let data: Vec<u8> = {
+            let segment = self.data_segments.get(segment_index).ok_or_else(|| {
+                ErrorKind::RuntimeError(format!("no data segment for index {}", segment_index))
+            })?;
+            match src.checked_add(len) {
+                Some(end) if end <= segment.len() => segment[src..end].to_vec(),
+                _ => {
+                    return Err(ErrorKind::RuntimeError(
+                        "out of bounds memory access".to_string(),
+                    ))
+                }
+            }
+        };
+        let memory = self.memory_mut(memory_index);
+        match dst.checked_add(len) {
+            Some(end) if end <= memory.current_size() => {
+                memory[dst..end].copy_from_slice(&data);
+                Ok(())
+            }
+            _ => Err(ErrorKind::RuntimeError(
+                "out of bounds memory access".to_string(),
+            )),
+        }
+    }
+
+    /// Frees the data segment `segment_index`. Once dropped, the segment
+    /// behaves as an empty one (so only empty ranges of it can be used by `memory_init`).
+    pub fn drop_data_segment(&mut self, segment_index: usize) -> Result<(), ErrorKind> {
+        let segment = self.data_segments.get_mut(segment_index).ok_or_else(|| {
+            ErrorKind::RuntimeError(format!("no data segment for index {}", segment_index))
+        })?;
+        *segment = Vec::new();
+        Ok(())
+    }
+
+    /// Grows the memory `memory_index` so it has at least `min_pages` pages.
+    /// It does nothing if the memory is already that big.
+    pub fn ensure_memory_pages(&mut self, memory_index: usize, min_pages: u32) -> Result<(), ErrorKind> {
+        let current_pages = self
+            .memories
+            .get(memory_index)
+            .ok_or_else(|| ErrorKind::RuntimeError(format!("no memory for index {}", memory_index)))?
+            .current_pages();
+        if current_pages >= min_pages {
+            return Ok(());
+        }
+        self.grow_memory(memory_index, min_pages - current_pages)
+            .map(|_| ())
+            .ok_or_else(|| {
+                ErrorKind::RuntimeError(format!(
+                    "memory {} can't be grown to {} pages (maximum is {} pages)",
+                    memory_index,
+                    min_pages,
+                    self.memories[memory_index].maximum_size()
+                ))
+            })
+    }
+
+    /// Grows every memory so it has at least `min_pages` pages.
+    pub fn grow_all_memories_to(&mut self, min_pages: u32) -> Result<(), ErrorKind> {
+        for memory_index in 0..self.memories.len() {
+            self.ensure_memory_pages(memory_index, min_pages)?;
+        }
+        Ok(())
+    }
+
+    pub fn memories(&self) -> Arc<Vec<LinearMemory>> {
+        self.memories.clone()
+    }
+
+    fn tables_mut(&mut self) -> &mut Vec<Vec<usize>> {
+        Arc::get_mut(&mut self.tables).unwrap_or_else(|| {
+            panic!("Can't get tables as a mutable pointer (there might exist more mutable pointers to the tables)")
+        })
+    }
+
+    /// Returns the current number of elements of the table `table_index`.
+    pub fn table_size(&self, table_index: usize) -> Option<usize> {
+        self.tables.get(table_index).map(|table| table.len())
+    }
+
+    /// Returns the value of the element `elem` in the table `table_index`,
+    /// or `None` if any of the indexes is out of bounds.
+    /// Empty (null) table elements hold the value `0`.
+    pub fn table_get(&self, table_index: usize, elem: usize) -> Option<usize> {
+        self.tables.get(table_index)?.get(elem).cloned()
+    }
+
+    /// Sets the element `elem` in the table `table_index` to `value`
+    /// (usually a function address, or `0` to clear it).
+    pub fn table_set(
+        &mut self,
+        table_index: usize,
+        elem: usize,
+        value: usize,
+    ) -> Result<(), ErrorKind> {
+        let table = self
+            .tables_mut()
+            .get_mut(table_index)
+            .ok_or_else(|| ErrorKind::RuntimeError(format!("no table for index {}", table_index)))?;
+        let table_len = table.len();
+        match table.get_mut(elem) {
+            Some(element) => {
+                *element = value;
+                Ok(())
+            }
+            None => Err(ErrorKind::RuntimeError(format!(
+                "table element {} out of bounds (table {} has {} elements)",
+                elem, table_index, table_len
+            ))),
+        }
+    }
+
+    /// Replaces the imported functions of the instance with the ones of
+    /// `import_object`, without recompiling. The calls to the imports and
+    /// the table elements pointing to them are updated.
+    ///
+    /// Only the kind of the imports can be checked (functions are provided as
+    /// raw pointers), so the new functions must have the imported signatures.
+    pub fn set_imports(
+        &mut self,
+        module: &Module,
+        import_object: ImportObject<&str, &str>,
+        mock_missing_imports: bool,
+    ) -> Result<(), ErrorKind> {
+        let new_functions =
+            resolve_import_functions(module, &import_object, mock_missing_imports)?;
+        let old_functions = mem::replace(&mut self.import_functions, new_functions);
+
+        for (func_index, reloc) in &self.import_relocations {
+            let target = match reloc.target {
+                RelocationType::Normal(index) => self.import_functions[index as usize],
+                _ => unreachable!("only the relocations to imports are kept"),
+            };
+            let func_addr = self.get_function_pointer(*func_index);
+            apply_relocation(func_addr, reloc, target as isize);
+        }
+
+        let new_functions = self.import_functions.clone();
+        let replace_import = |element: &mut usize| {
+            if let Some(index) = old_functions.iter().position(|f| *f as usize == *element) {
+                *element = new_functions[index] as usize;
+            }
+        };
+        for table in self.tables_mut().iter_mut() {
+            table.iter_mut().for_each(&replace_import);
+        }
+        for segment in self.element_segments.iter_mut() {
+            segment.iter_mut().for_each(&replace_import);
+        }
+        Ok(())
+    }
+
+    pub fn get_function_pointer(&self, func_index: FuncIndex) -> *const u8 {
+        get_function_addr(&func_index, &self.import_functions, &self.functions)
+    }
+
+    /// Returns the native stack frame information computed while compiling
+    /// the function `func_index`, or `None` if it's an imported function.
+    pub fn function_frame_info(&self, func_index: FuncIndex) -> Option<FrameInfo> {
+        let index = func_index.index().checked_sub(self.import_functions.len())?;
+        self.frame_infos.get(index).cloned()
+    }
+
+    /// Returns the name, address and size in bytes of the machine code of each
+    /// local function, to feed external symbolizers (e.g. for writing a
+    /// `/tmp/perf-<pid>.map` file).
+    pub fn symbol_map(&self, module: &Module) -> Vec<(String, *const u8, usize)> {
+        self.functions
+            .iter()
+            .zip(self.code_sizes.iter())
+            .enumerate()
+            .map(|(i, (address, size))| {
+                let func_index = module.func_index(DefinedFuncIndex::new(i));
+                (module.function_name(func_index), *address, *size)
+            })
+            .collect()
+    }
+
+    /// Returns the SHA-256 digest of the target and of the machine code and
+    /// relocations of every function, before they're relocated. Compiling the
+    /// same module with the same settings gives the same hash, so it can be
+    /// used for checking that the compilation is reproducible. The addresses
+    /// of the counters embedded in the code by the `coverage`, `max_call_depth`
+    /// and `metering` options are normalized, so they don't change the hash.
+    pub fn artifact_hash(&self) -> [u8; 32] {
+        self.artifact_hash
+    }
+
+    /// Returns the number of calls to each of the local functions (indexed by
+    /// `DefinedFuncIndex`), or an empty vector if `coverage` wasn't enabled.
+    pub fn coverage(&self) -> Vec<u64> {
+        self.coverage_counters.clone()
+    }
+
+    /// Resets the call counts returned by `coverage` to zero.
+    pub fn reset_coverage(&mut self) {
+        for count in &mut self.coverage_counters {
+            *count = 0;
+        }
+    }
+
+    /// Marks `address..address + len` of the memory 0 as allocated in the
+    /// shadow heap, usually from the host implementation of `malloc`.
+    pub fn shadow_allocate(&mut self, address: u32, len: u32) -> Result<(), ErrorKind> {
+        match self.shadow_heap {
+            Some(ref mut shadow_heap) => shadow_heap.allocate(address, len),
+            None => Err(ErrorKind::RuntimeError(
+                "the instance has no shadow heap".to_string(),
+            )),
+        }
+    }
+
+    /// Marks the allocation at `address` as freed in the shadow heap,
+    /// usually from the host implementation of `free`.
+    pub fn shadow_free(&mut self, address: u32) -> Result<(), ErrorKind> {
+        match self.shadow_heap {
+            Some(ref mut shadow_heap) => shadow_heap.free(address),
+            None => Err(ErrorKind::RuntimeError(
+                "the instance has no shadow heap".to_string(),
+            )),
+        }
+    }
+
+    /// Starts or stops recording the time spent in every local function
+    /// during the calls. The instance must have been created with `profiling`.
+    pub fn profile(&self, enabled: bool) -> Result<(), ErrorKind> {
+        if !self.profiling {
+            return Err(ErrorKind::RuntimeError(
+                "the instance wasn't compiled for profiling".to_string(),
+            ));
+        }
+        self.profiler.borrow_mut().set_enabled(enabled);
+        Ok(())
+    }
+
+    /// Returns the calls recorded since profiling started (or since the last
+    /// `take_profile`), which can be exported with `ProfileTree::collapsed_stacks`.
+    pub fn take_profile(&self) -> ProfileTree {
+        self.profiler.borrow_mut().take()
+    }
+
+    /// Returns the time spent compiling each local function in the collapsed
+    /// stack format (`compile;<function name> <microseconds>`, one function per line),
+    /// that can be turned into a flamegraph with tools like `inferno` or FlameGraph.
+    pub fn compilation_profile(&self, module: &Module) -> String {
+        let mut profile = String::new();
+        for (i, compile_time) in self.compile_times.iter().enumerate() {
+            let func_index = module.func_index(DefinedFuncIndex::new(i));
+            let micros =
+                compile_time.as_secs() * 1_000_000 + u64::from(compile_time.subsec_micros());
+            profile.push_str(&format!(
+                "compile;{} {}\n",
+                module.function_name(func_index),
+                micros
+            ));
+        }
+        profile
+    }
+
+    fn enter_float_env(&self) -> Option<FloatEnv> {
+        if self.strict_float {
+            Some(float_env::enter_strict_float())
+        } else {
+            None
+        }
+    }
+
+    fn restore_float_env(&self, env: Option<FloatEnv>) {
+        if let Some(env) = env {
+            float_env::restore_float_env(env);
+        }
+    }
+
+    pub fn start(&self) -> Result<(), ErrorKind> {
+        if let Some(func_index) = self.start_func {
+            let func: fn(&Instance) = get_instance_function!(&self, func_index);
+            let depth = self.call_depth.get();
+            let profile_depth = self.profiler.borrow().depth();
+            let float_env = self.enter_float_env();
+            let result = call_protected!(func(self));
+            self.restore_float_env(float_env);
+            // A trap skips the decrements of the call depth and the profile exits
+            self.call_depth.set(depth);
+            self.profiler.borrow_mut().unwind(profile_depth);
+            result
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Calls the exported function `name` with `args` and returns its results.
+    pub fn execute_fn(
+        &self,
+        module: &Module,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>, ErrorKind> {
+        // WebAssembly functions return at most one value
+        let mut results = [Value::I32(0)];
+        let count = self.execute_fn_into(module, name, args, &mut results)?;
+        Ok(results[..count].to_vec())
+    }
+
+    /// Calls the exported function `name` with `args` and returns its results,
+    /// then rolls back the memories, globals and tables to their state before
+    /// the call (also when the function traps), so the call has no visible
+    /// side effects on the instance. The state is saved by copying it, so
+    /// this is meant for occasional "what would it return" queries.
+    pub fn execute_fn_readonly(
+        &mut self,
+        module: &Module,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>, ErrorKind> {
+        let saved_memories: Vec<(u32, Vec<u8>)> = self
+            .memories
+            .iter()
+            .map(|memory| (memory.current_pages(), memory.to_vec()))
+            .collect();
+        let saved_globals = self.globals.clone();
+        let saved_tables: Vec<Vec<usize>> = (*self.tables).clone();
+
+        let result = self.execute_fn(module, name, args);
+
+        // The memories, globals and tables are restored in place, so the
+        // pointers used by the compiled code stay valid
+        for (memory_index, (pages, bytes)) in saved_memories.into_iter().enumerate() {
+            if self.memories[memory_index].current_pages() > pages {
+                let memory = self.memory_mut(memory_index);
+                memory.shrink_to(pages);
+                let size = memory.current_size();
+                self.memories_pointer[memory_index].len = size;
+            }
+            self.memory_mut(memory_index).copy_from_slice(&bytes);
+        }
+        self.globals.copy_from_slice(&saved_globals);
+        for (table, saved_table) in self.tables_mut().iter_mut().zip(saved_tables) {
+            table.copy_from_slice(&saved_table);
+        }
+        result
+    }
+
+    /// Sets the gas charged for each kind of instruction by the metered code,
+    /// for the next calls. The instance must have been created with `metering`.
+    pub fn set_fuel_costs(&mut self, costs: CostTable) -> Result<(), ErrorKind> {
+        if !self.metering {
+            return Err(ErrorKind::RuntimeError(
+                "the instance doesn't meter the gas".to_string(),
+            ));
+        }
+        *self.gas_costs = costs.weights();
+        Ok(())
+    }
+
+    /// Calls the exported function `name` with `args`, letting it use up to
+    /// `gas_limit` units of gas. Returns its results and the gas it used, or
+    /// traps with `OutOfGas` if it goes over the limit. The instance must have
+    /// been created with `metering`.
+    pub fn execute_fn_metered(
+        &self,
+        module: &Module,
+        name: &str,
+        args: &[Value],
+        gas_limit: u64,
+    ) -> Result<(Vec<Value>, u64), ErrorKind> {
+        if !self.metering {
+            return Err(ErrorKind::RuntimeError(
+                "the instance doesn't meter the gas".to_string(),
+            ));
+        }
+        // Metered calls may be nested (through the imports), so the outer
+        // call counters are restored afterwards
+        let (outer_used, outer_limit) = (self.gas_used.get(), self.gas_limit.get());
+        self.gas_used.set(0);
+        self.gas_limit.set(gas_limit);
+        let results = self.execute_fn(module, name, args);
+        let used = self.gas_used.get();
+        self.gas_used.set(outer_used.saturating_add(used));
+        self.gas_limit.set(outer_limit);
+        Ok((results?, used))
+    }
+
+    /// Calls the exported function `name` with `args`, writing its results
+    /// into `results` and returning the number of results written.
+    /// Unlike `execute_fn` it doesn't allocate, so it can be used for
+    /// calling a function in a hot loop.
+    pub fn execute_fn_into(
+        &self,
+        module: &Module,
+        name: &str,
+        args: &[Value],
+        results: &mut [Value],
+    ) -> Result<usize, ErrorKind> {
+        let func_index = match module.info.exports.get(name) {
+            Some(&Export::Function(func_index)) => func_index,
+            _ => {
+                return Err(ErrorKind::LinkError(format!(
+                    "no exported function named {}",
+                    name
+                )))
+            }
+        };
+        let sig_index = module.info.functions[func_index].entity;
+        let signature = &module.info.signatures[sig_index.index()];
+
+        let param_types: Vec<_> = signature.params.iter().map(|p| p.value_type).collect();
+        let arg_types: Vec<_> = args.iter().map(|arg| arg.ty()).collect();
+        if param_types != arg_types {
+            return Err(ErrorKind::RuntimeError(format!(
+                "function {} expects arguments {:?}, received {:?}",
+                name, param_types, arg_types
+            )));
+        }
+        if results.len() < signature.returns.len() {
+            return Err(ErrorKind::RuntimeError(format!(
+                "function {} returns {} values, but the results buffer only holds {}",
+                name,
+                signature.returns.len(),
+                results.len()
+            )));
+        }
+
+        let mut ints = [0u64; MAX_INT_ARGS + 1];
+        let mut floats = [0f64; MAX_FLOAT_ARGS];
+        let (mut int_count, mut float_count) = (0, 0);
+        for arg in args {
+            if arg.ty().is_float() {
+                if float_count == MAX_FLOAT_ARGS {
+                    return Err(ErrorKind::RuntimeError(format!(
+                        "function {} has more than {} float parameters",
+                        name, MAX_FLOAT_ARGS
+                    )));
+                }
+                floats[float_count] = f64::from_bits(arg.to_bits());
+                float_count += 1;
+            } else {
+                if int_count == MAX_INT_ARGS {
+                    return Err(ErrorKind::RuntimeError(format!(
+                        "function {} has more than {} integer parameters",
+                        name, MAX_INT_ARGS
+                    )));
+                }
+                ints[int_count] = arg.to_bits();
+                int_count += 1;
+            }
+        }
+        // The vmctx is passed after the WebAssembly arguments
+        ints[int_count] = self as *const Instance as u64;
+
+        let func_addr = self.get_function_pointer(func_index);
+        let (i, f) = (ints, floats);
+        let return_type = signature.returns.first().map(|r| r.value_type);
+        let depth = self.call_depth.get();
+        let profile_depth = self.profiler.borrow().depth();
+        let float_env = self.enter_float_env();
+        let bits = match return_type {
+            Some(ty) if ty.is_float() => {
+                let func: FloatRetFn = unsafe { mem::transmute(func_addr) };
+                call_protected!(func(
+                    i[0], i[1], i[2], i[3], i[4], i[5], f[0], f[1], f[2], f[3], f[4], f[5], f[6],
+                    f[7]
+                ))
+                .map(f64::to_bits)
+            }
+            _ => {
+                let func: IntRetFn = unsafe { mem::transmute(func_addr) };
+                call_protected!(func(
+                    i[0], i[1], i[2], i[3], i[4], i[5], f[0], f[1], f[2], f[3], f[4], f[5], f[6],
+                    f[7]
+                ))
+            }
+        };
+        self.restore_float_env(float_env);
+        // A trap skips the decrements of the call depth and the profile exits
+        self.call_depth.set(depth);
+        self.profiler.borrow_mut().unwind(profile_depth);
+        let bits = bits?;
+
+        match return_type {
+            Some(ty) => {
+                results[0] = Value::from_bits(ty, bits).ok_or_else(|| {
+                    ErrorKind::RuntimeError(format!("unsupported return type {}", ty))
+                })?;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Calls the exported function `name` with `args`, then reads the values
+    /// it wrote through the pointer arguments described by `out_params`.
+    /// Returns the results of the function and the values of the out-params.
+    pub fn execute_with_outparams(
+        &self,
+        module: &Module,
+        name: &str,
+        args: &[Value],
+        out_params: &[OutParam],
+    ) -> Result<(Vec<Value>, Vec<Value>), ErrorKind> {
+        let results = self.execute_fn(module, name, args)?;
+        let memory = self
+            .memories
+            .get(0)
+            .ok_or_else(|| ErrorKind::RuntimeError("no memory for index 0".to_string()))?;
+        let mut out_values = Vec::with_capacity(out_params.len());
+        for out_param in out_params {
+            let address = args
+                .get(out_param.arg_index)
+                .and_then(Value::as_u32)
+                .ok_or_else(|| {
+                    ErrorKind::RuntimeError(format!(
+                        "the argument {} isn't an i32 pointer",
+                        out_param.arg_index
+                    ))
+                })? as usize;
+            let size = out_param.ty.bytes() as usize;
+            let bytes = memory.get(address..address + size).ok_or_else(|| {
+                ErrorKind::RuntimeError("out of bounds memory access".to_string())
+            })?;
+            let bits = bytes
+                .iter()
+                .enumerate()
+                .fold(0u64, |bits, (i, byte)| bits | u64::from(*byte) << (8 * i));
+            let value = Value::from_bits(out_param.ty, bits).ok_or_else(|| {
+                ErrorKind::RuntimeError(format!("unsupported out-param type {}", out_param.ty))
+            })?;
+            out_values.push(value);
+        }
+        Ok((results, out_values))
+    }
+
+    /// Declares that the pointers returned by the exported function `name`
+    /// point into the memory `memory_index` (by default, the memory 0).
+    pub fn associate_result_memory(
+        &mut self,
+        module: &Module,
+        name: &str,
+        memory_index: usize,
+    ) -> Result<(), ErrorKind> {
+        match module.info.exports.get(name) {
+            Some(Export::Function(_)) => {}
+            _ => {
+                return Err(ErrorKind::LinkError(format!(
+                    "no exported function named {}",
+                    name
+                )))
+            }
+        }
+        if memory_index >= self.memories.len() {
+            return Err(ErrorKind::LinkError(format!(
+                "no memory for index {}",
+                memory_index
+            )));
+        }
+        self.result_memories.insert(name.to_string(), memory_index);
+        Ok(())
+    }
+
+    /// Returns the index of the memory the pointers returned by the
+    /// exported function `name` point into.
+    pub fn result_memory(&self, name: &str) -> usize {
+        self.result_memories.get(name).cloned().unwrap_or(0)
+    }
+
+    /// Returns the ranges of the memory `memory_index` initialized by the
+    /// data segments, sorted and merged. The rest of the memory was zero
+    /// at instantiation.
+    pub fn initialized_ranges(&self, memory_index: usize) -> Vec<Range<usize>> {
+        self.initialized_ranges
+            .get(memory_index)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Calls every exported function of the module, in declaration order, with
+    /// the arguments built by `arg_provider` from its signature, and returns
+    /// the result (or the trap) of each call. With deterministic arguments,
+    /// the output is stable, so it can be compared against golden files.
+    pub fn exercise_all<F>(
+        &self,
+        module: &Module,
+        arg_provider: F,
+    ) -> Vec<(String, Result<Vec<Value>, ErrorKind>)>
+    where
+        F: Fn(&Signature) -> Vec<Value>,
+    {
+        module
+            .exported_functions_in_order()
+            .into_iter()
+            .map(|(name, signature)| {
+                let args = arg_provider(&signature);
+                let result = self.execute_fn(module, &name, &args);
+                (name, result)
+            })
+            .collect()
+    }
+
+    /// Binds the first argument of the exported function `name` to `first`,
+    /// so the calls only supply the remaining arguments.
+    pub fn bind_first<'a>(
+        &'a self,
+        module: &'a Module,
+        name: &str,
+        first: Value,
+    ) -> Result<BoundFunction<'a>, ErrorKind> {
+        let func_index = match module.info.exports.get(name) {
+            Some(&Export::Function(func_index)) => func_index,
+            _ => {
+                return Err(ErrorKind::LinkError(format!(
+                    "no exported function named {}",
+                    name
+                )))
+            }
+        };
+        let sig_index = module.info.functions[func_index].entity;
+        let signature = &module.info.signatures[sig_index.index()];
+        match signature.params.first() {
+            Some(param) if param.value_type == first.ty() => {}
+            param => {
+                return Err(ErrorKind::RuntimeError(format!(
+                    "function {} expects a first argument of type {:?}, received {:?}",
+                    name,
+                    param.map(|p| p.value_type),
+                    first
+                )))
+            }
+        }
+        Ok(BoundFunction {
+            instance: self,
+            module,
+            name: name.to_string(),
+            first,
+        })
+    }
+
+    /// Calls the exported function `name` with `args`, and returns the
+    /// NUL-terminated string pointed by its result, in its result memory.
+    pub fn execute_fn_cstr(
+        &self,
+        module: &Module,
+        name: &str,
+        args: &[Value],
+    ) -> Result<String, ErrorKind> {
+        let results = self.execute_fn(module, name, args)?;
+        let address = results
+            .first()
+            .and_then(Value::as_u32)
+            .ok_or_else(|| {
+                ErrorKind::RuntimeError(format!("function {} doesn't return an i32 pointer", name))
+            })? as usize;
+        let memory_index = self.result_memory(name);
+        let memory = self.memories.get(memory_index).ok_or_else(|| {
+            ErrorKind::RuntimeError(format!("no memory for index {}", memory_index))
+        })?;
+        let bytes = memory.get(address..).unwrap_or(&[]);
+        let len = bytes.iter().position(|byte| *byte == 0).ok_or_else(|| {
+            ErrorKind::RuntimeError("unterminated string in memory".to_string())
+        })?;
+        Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+
+    /// Calls the exported function `name` with `args`, expecting it to trap
+    /// with the `expected` code. Returns a description of what happened otherwise.
+    pub fn expect_trap(
+        &self,
+        module: &Module,
+        name: &str,
+        args: &[Value],
+        expected: trap::TrapCode,
+    ) -> Result<(), String> {
+        match self.execute_fn(module, name, args) {
+            Err(ErrorKind::Trap(code, _)) if code == expected => Ok(()),
+            Err(ErrorKind::Trap(code, message)) => Err(format!(
+                "{} trapped with {:?} ({}), expected {:?}",
+                name, code, message, expected
+            )),
+            Err(e) => Err(format!("{} failed without trapping: {}", name, e)),
+            Ok(results) => Err(format!(
+                "{} returned {:?}, expected a {:?} trap",
+                name, results, expected
+            )),
+        }
+    }
+
+    /// Calls every exported function without parameters whose name starts
+    /// with `prefix` (e.g. `test_`), in name order. A trap only fails the
+    /// function that raised it, the remaining functions are still called.
+    pub fn run_exports_matching(
+        &self,
+        module: &Module,
+        prefix: &str,
+    ) -> Vec<(String, Result<Vec<Value>, ErrorKind>)> {
+        module
+            .exported_functions_with_types()
+            .into_iter()
+            .filter(|(name, signature)| name.starts_with(prefix) && signature.params.is_empty())
+            .map(|(name, _)| {
+                let result = self.execute_fn(module, &name, &[]);
+                (name, result)
+            })
+            .collect()
+    }
+
+    /// Returns a slice of the contents of allocated linear memory.
+    pub fn inspect_memory(&self, memory_index: usize, address: usize, len: usize) -> &[u8] {
+        &self
+            .memories
+            .get(memory_index)
+            .unwrap_or_else(|| panic!("no memory for index {}", memory_index))
+            .as_ref()[address..address + len]
+    }
+
+    /// Returns the base address and the current length (in bytes) of the
+    /// memory `memory_index`, for handing it off to native code without copying.
+    ///
+    /// The returned pointer must not be used across calls into WebAssembly:
+    /// any `grow_memory` may change the memory length.
+    pub fn memory_base_ptr(&self, memory_index: usize) -> Option<(*const u8, usize)> {
+        self.memories
+            .get(memory_index)
+            .map(|mem| (mem[..].as_ptr(), mem.current_size()))
+    }
+
+    /// Writes `values` as little-endian at `offset` in the memory `memory_index`.
+    /// Much faster than writing the values one by one for large arrays.
+    pub fn write_slice_le<T: Pod>(
+        &mut self,
+        memory_index: usize,
+        offset: usize,
+        values: &[T],
+    ) -> Result<(), ErrorKind> {
+        if memory_index >= self.memories.len() {
+            return Err(ErrorKind::RuntimeError(format!(
+                "no memory for index {}",
+                memory_index
+            )));
+        }
+        self.memory_mut(memory_index).write_slice_le(offset, values)
+    }
+
+    /// Writes each value as little-endian at its offset in the memory
+    /// `memory_index`. All the offsets are bounds checked before writing
+    /// anything, so the memory is left untouched if any of them is invalid.
+    pub fn write_values(
+        &mut self,
+        memory_index: usize,
+        values: &[(usize, Value)],
+    ) -> Result<(), ErrorKind> {
+        let memory_size = self
+            .memories
+            .get(memory_index)
+            .ok_or_else(|| ErrorKind::RuntimeError(format!("no memory for index {}", memory_index)))?
+            .current_size();
+        for (offset, value) in values {
+            match offset.checked_add(value.ty().bytes() as usize) {
+                Some(end) if end <= memory_size => {}
+                _ => {
+                    return Err(ErrorKind::RuntimeError(format!(
+                        "out of bounds memory access writing {:?} at {}",
+                        value, offset
+                    )))
+                }
+            }
+        }
+        let memory = self.memory_mut(memory_index);
+        for (offset, value) in values {
+            let bits = value.to_bits();
+            for i in 0..value.ty().bytes() as usize {
+                memory[offset + i] = (bits >> (8 * i)) as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the little-endian values at `offset` in the memory `memory_index`
+    /// into `values`.
+    pub fn read_slice_le<T: Pod>(
+        &self,
+        memory_index: usize,
+        offset: usize,
+        values: &mut [T],
+    ) -> Result<(), ErrorKind> {
+        self.memories
+            .get(memory_index)
+            .ok_or_else(|| ErrorKind::RuntimeError(format!("no memory for index {}", memory_index)))?
+            .read_slice_le(offset, values)
+    }
+
+    /// Returns the FNV-1a hash of the contents of the memory `memory_index`,
+    /// for telling quickly if two memories have the same contents.
+    pub fn memory_checksum(&self, memory_index: usize) -> Option<u64> {
+        self.memories
+            .get(memory_index)
+            .map(|memory| fnv1a_hash(&memory[..]))
+    }
+
+    pub fn memory_offset_addr(&self, index: usize, offset: usize) -> *const usize {
+        let memories: &[LinearMemory] = &self.memories[..];
+        let mem = &memories[index];
+        unsafe { mem[..].as_ptr().add(offset) as *const usize }
+    }
+
+    /// Returns the raw value of the global `global_index`.
+    /// Every global is stored in 8 bytes, so `i32` and `f32` globals
+    /// only use the lower 32 bits (`f32` and `f64` values are stored as bits).
+    pub fn get_global(&self, global_index: GlobalIndex) -> Option<i64> {
+        let offset = global_index.index() * 8;
+        if offset + 8 > self.globals.len() {
+            return None;
+        }
+        Some(unsafe { read_unaligned(self.globals[offset..].as_ptr() as *const i64) })
+    }
+
+    /// Sets the raw value of the global `global_index`.
+    pub fn set_global(&mut self, global_index: GlobalIndex, value: i64) -> Result<(), ErrorKind> {
+        let offset = global_index.index() * 8;
+        if offset + 8 > self.globals.len() {
+            return Err(ErrorKind::RuntimeError(format!(
+                "no global for index {}",
+                global_index.index()
+            )));
+        }
+        unsafe { write_unaligned(self.globals[offset..].as_mut_ptr() as *mut i64, value) };
+        Ok(())
+    }
+
+    /// Returns the current value of the shadow stack pointer: the `i32` global that
+    /// Emscripten and LLVM generated modules use to maintain a C stack in linear memory.
+    /// The global can be found with `Module::stack_pointer_global`.
+    pub fn shadow_stack_pointer(&self, global_index: GlobalIndex) -> Option<u32> {
+        self.get_global(global_index).map(|value| value as u32)
+    }
+
+    /// Sets the value of the shadow stack pointer global.
+    pub fn set_shadow_stack_pointer(
+        &mut self,
+        global_index: GlobalIndex,
+        stack_pointer: u32,
+    ) -> Result<(), ErrorKind> {
+        self.set_global(global_index, i64::from(stack_pointer))
+    }
+
+    /// Returns a JSON view of the instance state, meant for snapshot tests:
+    /// the globals (decoded by type), the size of each memory (with the
+    /// hash of its contents if `hash_memories` is set) and the length of each table.
+    pub fn dump_state_json(&self, module: &Module, hash_memories: bool) -> String {
+        let globals: Vec<String> = module
+            .info
+            .globals
+            .iter()
+            .enumerate()
+            .map(|(i, global)| {
+                let ty = global.entity.ty;
+                let value = self
+                    .get_global(GlobalIndex::new(i))
+                    .and_then(|bits| Value::from_bits(ty, bits as u64));
+                let value = match value {
+                    Some(Value::I32(x)) => x.to_string(),
+                    Some(Value::I64(x)) => x.to_string(),
+                    Some(Value::F32(x)) if x.is_finite() => format!("{:?}", x),
+                    Some(Value::F64(x)) if x.is_finite() => format!("{:?}", x),
+                    // JSON has no representation for NaN and the infinities
+                    Some(Value::F32(x)) => format!("\"{}\"", x),
+                    Some(Value::F64(x)) => format!("\"{}\"", x),
+                    None => "null".to_string(),
+                };
+                format!("    {{\"type\": \"{}\", \"value\": {}}}", ty, value)
+            })
+            .collect();
+
+        let memories: Vec<String> = self
+            .memories
+            .iter()
+            .enumerate()
+            .map(|(i, memory)| {
+                if hash_memories {
+                    format!(
+                        "    {{\"pages\": {}, \"hash\": \"{:016x}\"}}",
+                        memory.current_pages(),
+                        self.memory_checksum(i).unwrap()
+                    )
+                } else {
+                    format!("    {{\"pages\": {}}}", memory.current_pages())
+                }
+            })
+            .collect();
+
+        let tables: Vec<String> = self
+            .tables
+            .iter()
+            .map(|table| format!("    {{\"length\": {}}}", table.len()))
+            .collect();
+
+        format!(
+            "{{\n  \"globals\": [\n{}\n  ],\n  \"memories\": [\n{}\n  ],\n  \"tables\": [\n{}\n  ]\n}}\n",
+            globals.join(",\n"),
+            memories.join(",\n"),
+            tables.join(",\n")
+        )
+    }
+
+    // Shows the value of a global variable.
+    // pub fn inspect_global(&self, global_index: GlobalIndex, ty: ir::Type) -> &[u8] {
     //     let offset = global_index * 8;
     //     let len = ty.bytes() as usize;
     //     &self.globals[offset..offset + len]
@@ -556,6 +2646,21 @@ impl Instance {
     // }
 }
 
+// The raw pointers of an instance only point to the code, memories, tables
+// and globals that it owns, so it can be moved to another thread. The trap
+// recovery state is thread local, so calls on other threads don't interfere.
+unsafe impl Send for Instance {}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        // The memories zero themselves (see `LinearMemory::set_zero_on_drop`)
+        // once the last reference to them is dropped.
+        if self.zero_on_drop {
+            zero_volatile(&mut self.globals[..]);
+        }
+    }
+}
+
 // TODO: Needs to be moved to more appropriate place
 extern "C" fn grow_memory(size: u32, memory_index: u32, instance: &mut Instance) -> i32 {
     // TODO: Support for only one LinearMemory for now.
@@ -565,14 +2670,1312 @@ extern "C" fn grow_memory(size: u32, memory_index: u32, instance: &mut Instance)
     );
 
     let old_mem_size = instance
-        .memory_mut(memory_index as usize)
-        .grow(size)
+        .grow_memory(memory_index as usize, size)
         .unwrap_or(-1);
 
+    if old_mem_size == -1 && instance.trap_on_grow_failure {
+        unsafe { do_unwind(trap::MEMORY_GROW_FAILED) }
+    }
     old_mem_size
 }
 
+extern "C" fn call_stack_exhausted() {
+    unsafe { do_unwind(trap::CALL_STACK_EXHAUSTED) }
+}
+
+extern "C" fn shadow_check(address: u32, offset: u32, size: u32, instance: &Instance) {
+    // The effective address of the access doesn't wrap around
+    let address = u64::from(address) + u64::from(offset);
+    if let Some(ref shadow_heap) = instance.shadow_heap {
+        if !shadow_heap.check(address, u64::from(size)) {
+            unsafe { do_unwind(trap::INVALID_HEAP_ACCESS) }
+        }
+    }
+}
+
+extern "C" fn profile_enter(func_index: u32, instance: &Instance) {
+    instance
+        .profiler
+        .borrow_mut()
+        .enter(FuncIndex::new(func_index as usize));
+}
+
+extern "C" fn profile_exit(instance: &Instance) {
+    instance.profiler.borrow_mut().exit();
+}
+
+extern "C" fn integer_overflow() {
+    unsafe { do_unwind(trap::INTEGER_OVERFLOW) }
+}
+
+extern "C" fn out_of_gas() {
+    unsafe { do_unwind(trap::OUT_OF_GAS) }
+}
+
 extern "C" fn current_memory(memory_index: u32, instance: &mut Instance) -> u32 {
     let memory = &instance.memories[memory_index as usize];
     memory.current_pages() as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::compile;
+    use super::super::import_object::{ImportObject, ImportValue};
+    use super::super::memory::LinearMemory;
+    use super::super::errors::ErrorKind;
+    use super::super::module::Module;
+    use super::super::trap::TrapCode;
+    use super::super::value::Value;
+    use super::{CostTable, FootprintBreakdown, Instance, InstanceOptions, OutParam};
+    use cranelift_codegen::ir::types;
+    use cranelift_codegen::isa;
+    use cranelift_codegen::settings;
+    use cranelift_entity::EntityRef;
+    use cranelift_wasm::{FuncIndex, GlobalIndex};
+    use std::sync::Arc;
+    use std::thread;
+    use wabt::wat2wasm;
+
+    fn options() -> InstanceOptions {
+        let isa = isa::lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+        InstanceOptions::new(isa, true)
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_concurrent_instances_of_shared_module() {
+        assert_send_sync::<Module>();
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "bump") (param i32) (result i32)
+                i32.const 0
+                i32.const 0
+                i32.load
+                get_local 0
+                i32.add
+                i32.store
+                i32.const 0
+                i32.load))
+            "#,
+        ).unwrap();
+        let module = Arc::new(compile(wasm).unwrap());
+
+        let threads: Vec<_> = (0..8)
+            .map(|n| {
+                let module = module.clone();
+                thread::spawn(move || {
+                    let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+                    let mut result = Vec::new();
+                    for _ in 0..100 {
+                        result = instance
+                            .execute_fn(&module, "bump", &[Value::I32(n)])
+                            .unwrap();
+                    }
+                    result
+                })
+            }).collect();
+
+        // Every instance has its own memory, so they don't see each other's stores
+        for (n, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap(), vec![Value::I32(100 * n as i32)]);
+        }
+    }
+
+    #[test]
+    fn test_execute_fn_readonly() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (global $calls (mut i32) (i32.const 0))
+              (func $bump (export "bump") (result i32)
+                (set_global $calls (i32.add (get_global $calls) (i32.const 1)))
+                (i32.store (i32.const 0) (get_global $calls))
+                (drop (grow_memory (i32.const 1)))
+                (get_global $calls))
+              (func (export "bump_and_trap")
+                (drop (call $bump))
+                unreachable)
+              (func (export "calls") (result i32)
+                (i32.load (i32.const 0))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(
+                instance.execute_fn_readonly(&module, "bump", &[]).unwrap(),
+                vec![Value::I32(1)]
+            );
+        }
+        assert!(instance
+            .execute_fn_readonly(&module, "bump_and_trap", &[])
+            .is_err());
+        assert_eq!(instance.memories[0].current_pages(), 1);
+        assert_eq!(
+            instance.execute_fn(&module, "calls", &[]).unwrap(),
+            vec![Value::I32(0)]
+        );
+    }
+
+    #[test]
+    fn test_trap_on_grow_failure() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1 1)
+              (func (export "grow") (result i32)
+                (grow_memory (i32.const 1))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(
+            instance.execute_fn(&module, "grow", &[]).unwrap(),
+            vec![Value::I32(-1)]
+        );
+
+        let mut trapping_options = options();
+        trapping_options.trap_on_grow_failure = true;
+        let instance = Instance::new(&module, ImportObject::new(), trapping_options).unwrap();
+        match instance.execute_fn(&module, "grow", &[]) {
+            Err(ErrorKind::Trap(TrapCode::MemoryGrowFailed, _)) => {}
+            result => panic!("expected a grow failure trap, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_coverage() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func $f)
+              (func $g)
+              (func (export "run")
+                (call $f)
+                (call $f)))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut coverage_options = options();
+        coverage_options.coverage = true;
+        let mut instance = Instance::new(&module, ImportObject::new(), coverage_options).unwrap();
+        instance.execute_fn(&module, "run", &[]).unwrap();
+        assert_eq!(instance.coverage(), vec![2, 0, 1]);
+        instance.reset_coverage();
+        assert_eq!(instance.coverage(), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_execute_with_outparams() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "divmod") (param i32 i32 i32) (result i32)
+                (i64.store (get_local 2) (i64.extend_u/i32 (i32.rem_u (get_local 0) (get_local 1))))
+                (i32.div_u (get_local 0) (get_local 1))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let out_params = [OutParam {
+            arg_index: 2,
+            ty: types::I64,
+        }];
+        let args = [Value::I32(17), Value::I32(5), Value::I32(1000)];
+        assert_eq!(
+            instance
+                .execute_with_outparams(&module, "divmod", &args, &out_params)
+                .unwrap(),
+            (vec![Value::I32(3)], vec![Value::I64(2)])
+        );
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func $recurse (export "recurse") (param i32) (result i32)
+                (if (result i32) (get_local 0)
+                  (then (i32.add (call $recurse (i32.sub (get_local 0) (i32.const 1))) (i32.const 1)))
+                  (else (i32.const 0)))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut limited_options = options();
+        limited_options.max_call_depth = Some(10);
+        let instance = Instance::new(&module, ImportObject::new(), limited_options).unwrap();
+        // 10 nested calls: the initial one and 9 recursive ones
+        for _ in 0..2 {
+            assert_eq!(
+                instance.execute_fn(&module, "recurse", &[Value::I32(9)]).unwrap(),
+                vec![Value::I32(9)]
+            );
+            match instance.execute_fn(&module, "recurse", &[Value::I32(10)]) {
+                Err(ErrorKind::Trap(TrapCode::CallStackExhausted, _)) => {}
+                result => panic!("expected a call stack exhausted trap, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_shadow_heap() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "load") (param i32) (result i32)
+                (i32.load offset=4 (get_local 0))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut shadow_options = options();
+        shadow_options.shadow_heap = Some(1024..4096);
+        let mut instance = Instance::new(&module, ImportObject::new(), shadow_options).unwrap();
+        // Outside of the heap
+        assert!(instance.execute_fn(&module, "load", &[Value::I32(0)]).is_ok());
+        match instance.execute_fn(&module, "load", &[Value::I32(1024)]) {
+            Err(ErrorKind::Trap(TrapCode::InvalidHeapAccess, _)) => {}
+            result => panic!("expected an invalid heap access trap, got {:?}", result),
+        }
+        instance.shadow_allocate(1024, 8).unwrap();
+        assert!(instance.execute_fn(&module, "load", &[Value::I32(1024)]).is_ok());
+        instance.shadow_free(1024).unwrap();
+        assert!(instance.execute_fn(&module, "load", &[Value::I32(1024)]).is_err());
+    }
+
+    #[test]
+    fn test_execute_fn_cstr() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (data (i32.const 16) "hello\00")
+              (func (export "greeting") (result i32)
+                i32.const 16))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(instance.result_memory("greeting"), 0);
+        assert_eq!(
+            instance.execute_fn_cstr(&module, "greeting", &[]).unwrap(),
+            "hello"
+        );
+        assert!(instance.associate_result_memory(&module, "greeting", 1).is_err());
+        assert!(instance.associate_result_memory(&module, "missing", 0).is_err());
+    }
+
+    #[test]
+    fn test_artifact_hash() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func $double (param i32) (result i32)
+                (i32.mul (get_local 0) (i32.const 2)))
+              (func (export "quadruple") (param i32) (result i32)
+                (call $double (call $double (get_local 0)))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let first = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let second = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(first.artifact_hash(), second.artifact_hash());
+
+        let mut overflow_options = options();
+        overflow_options.trap_on_int_overflow = true;
+        let third = Instance::new(&module, ImportObject::new(), overflow_options).unwrap();
+        assert_ne!(first.artifact_hash(), third.artifact_hash());
+
+        let instrumented_options = || {
+            let mut instrumented_options = options();
+            instrumented_options.coverage = true;
+            instrumented_options.max_call_depth = Some(100);
+            instrumented_options.metering = true;
+            instrumented_options
+        };
+        let fourth = Instance::new(&module, ImportObject::new(), instrumented_options()).unwrap();
+        let fifth = Instance::new(&module, ImportObject::new(), instrumented_options()).unwrap();
+        assert_eq!(fourth.artifact_hash(), fifth.artifact_hash());
+        assert_ne!(first.artifact_hash(), fourth.artifact_hash());
+    }
+
+    #[test]
+    fn test_execute_fn_metered() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func (export "sum") (param i32) (result i32)
+                (local i32)
+                (block
+                  (loop
+                    (br_if 1 (i32.eqz (get_local 0)))
+                    (set_local 1 (i32.add (get_local 1) (get_local 0)))
+                    (set_local 0 (i32.sub (get_local 0) (i32.const 1)))
+                    (br 0)))
+                (get_local 1)))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut metered_options = options();
+        metered_options.metering = true;
+        let instance = Instance::new(&module, ImportObject::new(), metered_options).unwrap();
+
+        let (results, used) = instance
+            .execute_fn_metered(&module, "sum", &[Value::I32(10)], u64::max_value())
+            .unwrap();
+        assert_eq!(results, vec![Value::I32(55)]);
+        // The same call uses the same gas, and more iterations use more gas
+        let (_, used_again) = instance
+            .execute_fn_metered(&module, "sum", &[Value::I32(10)], u64::max_value())
+            .unwrap();
+        assert_eq!(used, used_again);
+        let (_, used_more) = instance
+            .execute_fn_metered(&module, "sum", &[Value::I32(20)], u64::max_value())
+            .unwrap();
+        assert!(used_more > used);
+
+        match instance.execute_fn_metered(&module, "sum", &[Value::I32(10)], used - 1) {
+            Err(ErrorKind::Trap(TrapCode::OutOfGas, _)) => {}
+            result => panic!("expected an out of gas trap, got {:?}", result),
+        }
+        assert!(instance
+            .execute_fn_metered(&module, "sum", &[Value::I32(10)], used)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_write_values() {
+        let wasm = wat2wasm("(module (memory 1))").unwrap();
+        let module = compile(wasm).unwrap();
+        let mut instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        instance
+            .write_values(0, &[(0, Value::I32(-2)), (4, Value::F32(1.5)), (8, Value::I64(1))])
+            .unwrap();
+        assert_eq!(
+            &instance.memories[0][0..12],
+            &[0xfe, 0xff, 0xff, 0xff, 0x00, 0x00, 0xc0, 0x3f, 0x01, 0x00, 0x00, 0x00]
+        );
+        // Nothing is written if any of the values is out of bounds
+        let last = LinearMemory::WASM_PAGE_SIZE - 4;
+        assert!(instance
+            .write_values(0, &[(0, Value::I32(7)), (last, Value::I64(0))])
+            .is_err());
+        assert_eq!(instance.memories[0][0], 0xfe);
+    }
+
+    #[test]
+    fn test_initializer_order() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "offset" (global i32))
+              (global i32 (get_global 0))
+              (memory 1)
+              (data (get_global 0) "hi"))
+            "#,
+        ).unwrap();
+        let mut module = compile(wasm).unwrap();
+        let mut import_object = ImportObject::new();
+        import_object.set("env", "offset", ImportValue::Global(32));
+        let instance = Instance::new(&module, import_object, options()).unwrap();
+        assert_eq!(&instance.memories[0][32..34], b"hi");
+
+        // The local global is only initialized after the imported ones
+        module.info.data_initializers[0].base = Some(GlobalIndex::new(1));
+        let mut import_object = ImportObject::new();
+        import_object.set("env", "offset", ImportValue::Global(32));
+        match Instance::new(&module, import_object, options()) {
+            Err(ErrorKind::InitializerOrderViolation(_)) => {}
+            _ => panic!("Expected an initializer order violation"),
+        }
+    }
+
+    #[test]
+    fn test_initialized_ranges() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (data (i32.const 16) "abcd")
+              (data (i32.const 18) "cdef")
+              (data (i32.const 22) "gh")
+              (data (i32.const 64) "xyz"))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(instance.initialized_ranges(0), vec![16..24, 64..67]);
+        assert!(instance.initialized_ranges(1).is_empty());
+    }
+
+    #[test]
+    fn test_bind_first() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (data (i32.const 8) "\05\00\00\00")
+              (func (export "add_to_field") (param i32 i32) (result i32)
+                (i32.add (i32.load (get_local 0)) (get_local 1))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let method = instance
+            .bind_first(&module, "add_to_field", Value::I32(8))
+            .unwrap();
+        assert_eq!(method.call(&[Value::I32(2)]).unwrap(), vec![Value::I32(7)]);
+        assert_eq!(method.call(&[Value::I32(-5)]).unwrap(), vec![Value::I32(0)]);
+        assert!(instance
+            .bind_first(&module, "add_to_field", Value::I64(8))
+            .is_err());
+    }
+
+    #[test]
+    fn test_total_footprint() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 2)
+              (table 4 anyfunc)
+              (global i64 (i64.const 1))
+              (global i32 (i32.const 2))
+              (func (export "f") (result i32) i32.const 0))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let footprint = instance.total_footprint();
+        assert!(footprint.code > 0);
+        assert_eq!(
+            footprint,
+            FootprintBreakdown {
+                code: footprint.code,
+                memory: 2 * 65536,
+                tables: 4 * 8,
+                globals: 2 * 8,
+            }
+        );
+
+        let mut capped_options = options();
+        capped_options.max_footprint_bytes = Some(65536);
+        assert!(Instance::new(&module, ImportObject::new(), capped_options).is_err());
+    }
+
+    #[test]
+    fn test_exercise_all() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "trap") unreachable)
+              (func (export "double") (param i32) (result i32)
+                (i32.mul (get_local 0) (i32.const 2)))
+              (func (export "answer") (result i64) i64.const 42))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let results = instance.exercise_all(&module, |signature| {
+            signature.params.iter().map(|_| Value::I32(21)).collect()
+        });
+        let names: Vec<_> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["trap", "double", "answer"]);
+        assert_eq!(results[1].1.as_ref().unwrap(), &vec![Value::I32(42)]);
+        assert_eq!(results[2].1.as_ref().unwrap(), &vec![Value::I64(42)]);
+        match results[0].1 {
+            Err(ErrorKind::Trap(TrapCode::IllegalInstruction, _)) => {}
+            ref other => panic!("Expected an unreachable trap, received {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_zero_on_drop() {
+        let wasm = wat2wasm("(module (memory 1))").unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert!(!instance.memories[0].zero_on_drop());
+
+        let mut zeroing_options = options();
+        zeroing_options.zero_on_drop = true;
+        let instance = Instance::new(&module, ImportObject::new(), zeroing_options).unwrap();
+        assert!(instance.memories[0].zero_on_drop());
+    }
+
+    #[test]
+    fn test_cancel_compilation() {
+        use std::sync::atomic::AtomicBool;
+
+        let wasm = wat2wasm("(module (func (export \"f\")))").unwrap();
+        let module = compile(wasm).unwrap();
+        let mut cancelled_options = options();
+        cancelled_options.cancel_compilation = Some(Arc::new(AtomicBool::new(true)));
+        match Instance::new(&module, ImportObject::new(), cancelled_options) {
+            Err(ErrorKind::CompilationCancelled) => {}
+            _ => panic!("Expected the compilation to be cancelled"),
+        }
+    }
+
+    #[test]
+    fn test_max_code_bytes() {
+        let wasm = wat2wasm("(module (func (export \"f\")))").unwrap();
+        let module = compile(wasm).unwrap();
+        let mut limited_options = options();
+        limited_options.max_code_bytes = Some(1);
+        match Instance::new(&module, ImportObject::new(), limited_options) {
+            Err(ErrorKind::CodeBudgetExceeded(1)) => {}
+            _ => panic!("Expected the code budget to be exceeded"),
+        }
+    }
+
+    #[test]
+    fn test_set_fuel_costs() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "load") (result i32)
+                (i32.load (i32.const 0))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut unmetered = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert!(unmetered.set_fuel_costs(CostTable::default()).is_err());
+
+        let mut metered_options = options();
+        metered_options.metering = true;
+        let mut instance = Instance::new(&module, ImportObject::new(), metered_options).unwrap();
+        let (_, used) = instance
+            .execute_fn_metered(&module, "load", &[], u64::max_value())
+            .unwrap();
+        instance
+            .set_fuel_costs(CostTable {
+                memory: 101,
+                ..CostTable::default()
+            })
+            .unwrap();
+        let (_, weighted) = instance
+            .execute_fn_metered(&module, "load", &[], u64::max_value())
+            .unwrap();
+        // The function has a single load
+        assert_eq!(weighted, used + 100);
+    }
+
+    #[test]
+    fn test_profile() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func $leaf (param i32) (result i32)
+                (i32.add (get_local 0) (i32.const 1)))
+              (func (export "run") (param i32) (result i32)
+                (call $leaf (call $leaf (get_local 0))))
+              (func (export "crash") (drop (call $leaf (i32.const 0))) unreachable))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert!(instance.profile(true).is_err());
+
+        let mut profiled_options = options();
+        profiled_options.profiling = true;
+        let instance = Instance::new(&module, ImportObject::new(), profiled_options).unwrap();
+        // Nothing is recorded until profiling starts
+        instance.execute_fn(&module, "run", &[Value::I32(1)]).unwrap();
+        assert!(instance.take_profile().roots.is_empty());
+
+        instance.profile(true).unwrap();
+        instance.execute_fn(&module, "run", &[Value::I32(1)]).unwrap();
+        assert!(instance.execute_fn(&module, "crash", &[]).is_err());
+        let profile = instance.take_profile();
+        let calls: Vec<_> = profile
+            .roots
+            .iter()
+            .map(|root| (root.func_index.index(), root.calls, root.children.len()))
+            .collect();
+        assert_eq!(calls, vec![(1, 1, 1), (2, 1, 1)]);
+        assert_eq!(profile.roots[0].children[0].calls, 2);
+        let stacks = profile.collapsed_stacks(&module);
+        let stacks: Vec<_> = stacks
+            .lines()
+            .map(|line| line.split(' ').next().unwrap())
+            .collect();
+        assert_eq!(
+            stacks,
+            vec!["run", "run;function_0", "crash", "crash;function_0"]
+        );
+    }
+
+    #[test]
+    fn test_trap_on_int_overflow() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func (export "add") (param i32 i32) (result i32)
+                (i32.add (get_local 0) (get_local 1))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let args = [Value::I32(i32::max_value()), Value::I32(1)];
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(
+            instance.execute_fn(&module, "add", &args).unwrap(),
+            vec![Value::I32(i32::min_value())]
+        );
+
+        let mut overflow_options = options();
+        overflow_options.trap_on_int_overflow = true;
+        let instance = Instance::new(&module, ImportObject::new(), overflow_options).unwrap();
+        match instance.execute_fn(&module, "add", &args) {
+            Err(ErrorKind::Trap(TrapCode::IntegerOverflow, _)) => {}
+            result => panic!("expected an integer overflow trap, got {:?}", result),
+        }
+        assert_eq!(
+            instance
+                .execute_fn(&module, "add", &[Value::I32(-1), Value::I32(1)])
+                .unwrap(),
+            vec![Value::I32(0)]
+        );
+    }
+
+    #[test]
+    fn test_function_frame_info() {
+        // The locals are all live across the call, so most of them are spilled
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func $f))
+              (func $empty (export "empty")
+                nop)
+              (func $spilled (export "spilled") (param i64) (result i64)
+                (local i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64 i64)
+                (set_local 1 (i64.add (get_local 0) (i64.const 1)))
+                (set_local 2 (i64.add (get_local 0) (i64.const 2)))
+                (set_local 3 (i64.add (get_local 0) (i64.const 3)))
+                (set_local 4 (i64.add (get_local 0) (i64.const 4)))
+                (set_local 5 (i64.add (get_local 0) (i64.const 5)))
+                (set_local 6 (i64.add (get_local 0) (i64.const 6)))
+                (set_local 7 (i64.add (get_local 0) (i64.const 7)))
+                (set_local 8 (i64.add (get_local 0) (i64.const 8)))
+                (set_local 9 (i64.add (get_local 0) (i64.const 9)))
+                (set_local 10 (i64.add (get_local 0) (i64.const 10)))
+                (set_local 11 (i64.add (get_local 0) (i64.const 11)))
+                (set_local 12 (i64.add (get_local 0) (i64.const 12)))
+                (set_local 13 (i64.add (get_local 0) (i64.const 13)))
+                (set_local 14 (i64.add (get_local 0) (i64.const 14)))
+                (set_local 15 (i64.add (get_local 0) (i64.const 15)))
+                (set_local 16 (i64.add (get_local 0) (i64.const 16)))
+                call $f
+                get_local 1
+                get_local 2 i64.add
+                get_local 3 i64.add
+                get_local 4 i64.add
+                get_local 5 i64.add
+                get_local 6 i64.add
+                get_local 7 i64.add
+                get_local 8 i64.add
+                get_local 9 i64.add
+                get_local 10 i64.add
+                get_local 11 i64.add
+                get_local 12 i64.add
+                get_local 13 i64.add
+                get_local 14 i64.add
+                get_local 15 i64.add
+                get_local 16 i64.add))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+
+        assert!(instance.function_frame_info(FuncIndex::new(0)).is_none());
+        let empty = instance.function_frame_info(FuncIndex::new(1)).unwrap();
+        assert_eq!(empty.locals_bytes, 0);
+        let spilled = instance.function_frame_info(FuncIndex::new(2)).unwrap();
+        // There are at most 6 callee-saved registers for the 16 locals
+        assert!(spilled.locals_bytes >= 10 * 8);
+        assert_eq!(spilled.locals_bytes % 8, 0);
+        assert!(spilled.max_stack >= spilled.locals_bytes);
+        assert!(spilled.max_stack > empty.max_stack);
+        assert!(instance.function_frame_info(FuncIndex::new(3)).is_none());
+    }
+
+    #[test]
+    fn test_table_get_set() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (type $t (func (result i32)))
+              (table 2 anyfunc)
+              (func $one (result i32) (i32.const 1))
+              (func $two (result i32) (i32.const 2))
+              (func (export "call") (param i32) (result i32)
+                (call_indirect (type $t) (get_local 0)))
+              (elem (i32.const 0) $one))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let one = instance.get_function_pointer(FuncIndex::new(0)) as usize;
+        let two = instance.get_function_pointer(FuncIndex::new(1)) as usize;
+        assert_eq!(instance.table_get(0, 0), Some(one));
+        assert_eq!(instance.table_get(0, 1), Some(0));
+
+        instance.table_set(0, 0, two).unwrap();
+        assert_eq!(instance.table_get(0, 0), Some(two));
+        assert_eq!(
+            instance.execute_fn(&module, "call", &[Value::I32(0)]).unwrap(),
+            vec![Value::I32(2)]
+        );
+
+        // Out of bounds elements and tables
+        assert_eq!(instance.table_get(0, 2), None);
+        assert_eq!(instance.table_get(1, 0), None);
+        assert!(instance.table_set(0, 2, one).is_err());
+        assert!(instance.table_set(1, 0, one).is_err());
+        assert_eq!(instance.table_get(0, 0), Some(two));
+    }
+
+    #[test]
+    fn test_force_explicit_bounds_checks() {
+        use super::super::compile_with_options;
+        use super::super::module::ModuleOptions;
+
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "load") (param i32) (result i32)
+                (i32.load (get_local 0)))
+              (data (i32.const 0) "\2a"))
+            "#,
+        ).unwrap();
+        let out_of_bounds = [Value::I32(LinearMemory::WASM_PAGE_SIZE as i32)];
+
+        // The explicit check traps at the access itself
+        let options_on = ModuleOptions {
+            force_explicit_bounds_checks: true,
+        };
+        let module = compile_with_options(wasm.clone(), options_on).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(
+            instance.execute_fn(&module, "load", &[Value::I32(0)]).unwrap(),
+            vec![Value::I32(42)]
+        );
+        match instance.execute_fn(&module, "load", &out_of_bounds) {
+            Err(ErrorKind::Trap(TrapCode::IllegalInstruction, _)) => {}
+            result => panic!("expected a bounds check trap, got {:?}", result),
+        }
+
+        // Without it, the access hits the guard pages
+        let module = compile_with_options(wasm, ModuleOptions::default()).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(
+            instance.execute_fn(&module, "load", &[Value::I32(0)]).unwrap(),
+            vec![Value::I32(42)]
+        );
+        match instance.execute_fn(&module, "load", &out_of_bounds) {
+            Err(ErrorKind::Trap(TrapCode::SegmentationViolation, _)) => {}
+            result => panic!("expected a guard page fault, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_table_bulk_operations() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (table 6 anyfunc)
+              (func $a)
+              (func $b)
+              (func $c)
+              (elem (i32.const 0) $a $b $c))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let a = instance.get_function_pointer(FuncIndex::new(0)) as usize;
+        let b = instance.get_function_pointer(FuncIndex::new(1)) as usize;
+        let c = instance.get_function_pointer(FuncIndex::new(2)) as usize;
+        let table = |instance: &Instance| -> Vec<usize> {
+            (0..instance.table_size(0).unwrap())
+                .map(|elem| instance.table_get(0, elem).unwrap())
+                .collect()
+        };
+        assert_eq!(table(&instance), vec![a, b, c, 0, 0, 0]);
+
+        // Overlapping copies, forwards and backwards
+        instance.table_copy(0, 0, 1, 0, 3).unwrap();
+        assert_eq!(table(&instance), vec![a, a, b, c, 0, 0]);
+        instance.table_copy(0, 0, 0, 1, 3).unwrap();
+        assert_eq!(table(&instance), vec![a, b, c, c, 0, 0]);
+
+        // Initialization from the element segment, skipping its first element
+        instance.table_init(0, 0, 4, 1, 2).unwrap();
+        assert_eq!(table(&instance), vec![a, b, c, c, b, c]);
+
+        instance.table_fill(0, 1, 0, 3).unwrap();
+        assert_eq!(table(&instance), vec![a, 0, 0, 0, b, c]);
+
+        // Out of range tables, segments and elements don't change anything
+        assert!(instance.table_copy(0, 0, 5, 0, 2).is_err());
+        assert!(instance.table_copy(0, 0, 0, 5, 2).is_err());
+        assert!(instance.table_copy(1, 0, 0, 0, 1).is_err());
+        assert!(instance.table_init(0, 0, 0, 2, 2).is_err());
+        assert!(instance.table_init(0, 0, 5, 0, 2).is_err());
+        assert!(instance.table_init(0, 1, 0, 0, 1).is_err());
+        assert!(instance.table_fill(0, 5, a, 2).is_err());
+        assert!(instance.table_fill(0, usize::max_value(), a, 2).is_err());
+        assert!(instance.table_fill(1, 0, a, 1).is_err());
+        assert_eq!(table(&instance), vec![a, 0, 0, 0, b, c]);
+    }
+
+    #[test]
+    fn test_memory_copy() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "load") (param i32) (result i32)
+                (i32.load8_u (get_local 0)))
+              (data (i32.const 0) "\01\02\03\04"))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+
+        // Overlapping ranges of the same memory
+        instance.memory_copy(0, 2, 0, 0, 4).unwrap();
+        assert_eq!(&instance.memories()[0][..6], &[1, 2, 1, 2, 3, 4]);
+        // The compiled code sees the copied bytes
+        assert_eq!(
+            instance.execute_fn(&module, "load", &[Value::I32(5)]).unwrap(),
+            vec![Value::I32(4)]
+        );
+
+        let end = LinearMemory::WASM_PAGE_SIZE;
+        instance.memory_copy(0, end - 4, 0, 0, 4).unwrap();
+        assert!(instance.memory_copy(0, end - 3, 0, 0, 4).is_err());
+        assert!(instance.memory_copy(0, 0, 0, end - 3, 4).is_err());
+        assert!(instance.memory_copy(0, usize::max_value(), 0, 0, 2).is_err());
+        assert!(instance.memory_copy(1, 0, 0, 0, 1).is_err());
+        assert!(instance.memory_copy(0, 0, 1, 0, 1).is_err());
+        assert_eq!(&instance.memories()[0][end - 4..], &[1, 2, 1, 2]);
+        assert_eq!(&instance.memories()[0][..6], &[1, 2, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_packed_code_placement() {
+        use super::{CodePlacement, PACKED_FUNCTION_ALIGN};
+
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func $double (param i32) (result i32)
+                (i32.mul (get_local 0) (i32.const 2)))
+              (func $nop)
+              (func (export "run") (param i32) (result i32)
+                (call $nop)
+                (call $double (i32.add (get_local 0) (i32.const 1)))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut packed_options = options();
+        packed_options.code_placement = CodePlacement::Packed;
+        let instance = Instance::new(&module, ImportObject::new(), packed_options).unwrap();
+
+        // Every function starts at the first aligned offset after the previous one
+        let symbols = instance.symbol_map(&module);
+        assert_eq!(symbols.len(), 3);
+        let base = symbols[0].1 as usize;
+        for pair in symbols.windows(2) {
+            let end = pair[0].1 as usize - base + pair[0].2;
+            let aligned_end =
+                (end + PACKED_FUNCTION_ALIGN - 1) / PACKED_FUNCTION_ALIGN * PACKED_FUNCTION_ALIGN;
+            assert_eq!(pair[1].1 as usize - base, aligned_end);
+        }
+        // The calls between the packed functions are relocated
+        assert_eq!(
+            instance.execute_fn(&module, "run", &[Value::I32(20)]).unwrap(),
+            vec![Value::I32(42)]
+        );
+    }
+
+    #[test]
+    fn test_unoptimized_functions() {
+        // The dead and redundant multiplications are only removed by the optimizations
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func (export "optimized") (param i32) (result i32)
+                (drop (i32.mul (get_local 0) (i32.const 3)))
+                (drop (i32.mul (get_local 0) (i32.const 5)))
+                (i32.add (i32.mul (get_local 0) (get_local 0))
+                         (i32.mul (get_local 0) (get_local 0))))
+              (func (export "unoptimized") (param i32) (result i32)
+                (drop (i32.mul (get_local 0) (i32.const 3)))
+                (drop (i32.mul (get_local 0) (i32.const 5)))
+                (i32.add (i32.mul (get_local 0) (get_local 0))
+                         (i32.mul (get_local 0) (get_local 0)))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut unoptimized_options = options();
+        unoptimized_options
+            .unoptimized_functions
+            .insert(FuncIndex::new(1));
+        let instance = Instance::new(&module, ImportObject::new(), unoptimized_options).unwrap();
+
+        let symbols = instance.symbol_map(&module);
+        assert!(symbols[1].2 > symbols[0].2);
+        for name in &["optimized", "unoptimized"] {
+            assert_eq!(
+                instance.execute_fn(&module, name, &[Value::I32(3)]).unwrap(),
+                vec![Value::I32(18)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_dump_state_json() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (global (mut i32) (i32.const -3))
+              (global f64 (f64.const 1.5))
+              (global f32 (f32.const inf))
+              (memory 1)
+              (table 2 anyfunc))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(
+            instance.dump_state_json(&module, false),
+            r#"{
+  "globals": [
+    {"type": "i32", "value": -3},
+    {"type": "f64", "value": 1.5},
+    {"type": "f32", "value": "inf"}
+  ],
+  "memories": [
+    {"pages": 1}
+  ],
+  "tables": [
+    {"length": 2}
+  ]
+}
+"#
+        );
+        let hashed = format!(
+            "{{\"pages\": 1, \"hash\": \"{:016x}\"}}",
+            instance.memory_checksum(0).unwrap()
+        );
+        assert!(instance.dump_state_json(&module, true).contains(&hashed));
+    }
+
+    #[test]
+    fn test_maximum_memory_pages() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1 10)
+              (func (export "grow") (param i32) (result i32)
+                (grow_memory (get_local 0))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut capped_options = options();
+        capped_options.maximum_memory_pages = Some(2);
+        let instance = Instance::new(&module, ImportObject::new(), capped_options).unwrap();
+        assert_eq!(instance.memories()[0].maximum_size(), 2);
+        assert_eq!(
+            instance.execute_fn(&module, "grow", &[Value::I32(2)]).unwrap(),
+            vec![Value::I32(-1)]
+        );
+        assert_eq!(
+            instance.execute_fn(&module, "grow", &[Value::I32(1)]).unwrap(),
+            vec![Value::I32(1)]
+        );
+
+        // The same module with the limit of its own memory
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(
+            instance.execute_fn(&module, "grow", &[Value::I32(2)]).unwrap(),
+            vec![Value::I32(1)]
+        );
+
+        // The cap can't be lower than the initial size of the memory
+        let mut capped_options = options();
+        capped_options.maximum_memory_pages = Some(0);
+        match Instance::new(&module, ImportObject::new(), capped_options) {
+            Err(ErrorKind::LinkError(_)) => {}
+            Err(e) => panic!("expected a link error, got {:?}", e),
+            Ok(_) => panic!("expected a link error"),
+        }
+    }
+
+    #[test]
+    fn test_expect_trap() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func (export "unreachable") unreachable)
+              (func (export "ok") (result i32) (i32.const 1)))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        assert_eq!(
+            instance.expect_trap(&module, "unreachable", &[], TrapCode::IllegalInstruction),
+            Ok(())
+        );
+        let wrong_code = instance
+            .expect_trap(&module, "unreachable", &[], TrapCode::OutOfGas)
+            .unwrap_err();
+        assert!(wrong_code.contains("trapped with IllegalInstruction"));
+        let no_trap = instance
+            .expect_trap(&module, "ok", &[], TrapCode::IllegalInstruction)
+            .unwrap_err();
+        assert!(no_trap.contains("returned [I32(1)]"));
+        assert!(instance
+            .expect_trap(&module, "missing", &[], TrapCode::IllegalInstruction)
+            .unwrap_err()
+            .contains("failed without trapping"));
+    }
+
+    extern "C" fn return_one() -> i32 {
+        1
+    }
+
+    extern "C" fn return_two() -> i32 {
+        2
+    }
+
+    #[test]
+    fn test_set_imports() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (type $t (func (result i32)))
+              (import "env" "f" (func $f (result i32)))
+              (table 1 anyfunc)
+              (elem (i32.const 0) $f)
+              (func (export "call") (result i32) (call $f))
+              (func (export "indirect") (result i32)
+                (call_indirect (type $t) (i32.const 0))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut import_object = ImportObject::new();
+        import_object.set("env", "f", ImportValue::Func(return_one as _));
+        let mut instance = Instance::new(&module, import_object, options()).unwrap();
+        for name in &["call", "indirect"] {
+            assert_eq!(instance.execute_fn(&module, name, &[]).unwrap(), vec![Value::I32(1)]);
+        }
+
+        // Both the direct calls and the table elements use the new import
+        let mut import_object = ImportObject::new();
+        import_object.set("env", "f", ImportValue::Func(return_two as _));
+        instance.set_imports(&module, import_object, false).unwrap();
+        for name in &["call", "indirect"] {
+            assert_eq!(instance.execute_fn(&module, name, &[]).unwrap(), vec![Value::I32(2)]);
+        }
+        assert_eq!(instance.table_get(0, 0), Some(return_two as usize));
+
+        // The imports of the wrong kind or missing are rejected
+        let mut import_object = ImportObject::new();
+        import_object.set("env", "f", ImportValue::Global(0));
+        assert!(instance.set_imports(&module, import_object, false).is_err());
+        assert!(instance.set_imports(&module, ImportObject::new(), false).is_err());
+        assert_eq!(instance.execute_fn(&module, "call", &[]).unwrap(), vec![Value::I32(2)]);
+    }
+
+    #[test]
+    fn test_memory_base_ptr() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1 2)
+              (func (export "grow") (result i32)
+                (grow_memory (i32.const 1)))
+              (data (i32.const 3) "\07"))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let (base, size) = instance.memory_base_ptr(0).unwrap();
+        assert_eq!(size, LinearMemory::WASM_PAGE_SIZE);
+        assert_eq!(unsafe { *base.add(3) }, 7);
+
+        // The base stays the same when the memory grows
+        instance.execute_fn(&module, "grow", &[]).unwrap();
+        assert_eq!(
+            instance.memory_base_ptr(0),
+            Some((base, 2 * LinearMemory::WASM_PAGE_SIZE))
+        );
+        assert_eq!(instance.memory_base_ptr(1), None);
+    }
+
+    #[test]
+    fn test_shadow_stack_pointer() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "STACKTOP" (global $stacktop i32))
+              (global $base (export "__stack_pointer") i32 (i32.const 1024))
+              (global $sp (mut i32) (i32.const 0))
+              (func (export "sp") (result i32) (get_global $sp)))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut import_object = ImportObject::new();
+        import_object.set("env", "STACKTOP", ImportValue::Global(64));
+        let mut instance = Instance::new(&module, import_object, options()).unwrap();
+
+        // Imported and exported stack pointers are found
+        let stacktop = module.stack_pointer_global("STACKTOP").unwrap();
+        assert_eq!(stacktop, GlobalIndex::new(0));
+        assert_eq!(instance.shadow_stack_pointer(stacktop), Some(64));
+        let base = module.stack_pointer_global("__stack_pointer").unwrap();
+        assert_eq!(base, GlobalIndex::new(1));
+        assert_eq!(instance.shadow_stack_pointer(base), Some(1024));
+        assert_eq!(module.stack_pointer_global("missing"), None);
+
+        // The compiled code sees the new value
+        let sp = GlobalIndex::new(2);
+        instance.set_shadow_stack_pointer(sp, 4096).unwrap();
+        assert_eq!(instance.get_global(sp), Some(4096));
+        assert_eq!(instance.execute_fn(&module, "sp", &[]).unwrap(), vec![Value::I32(4096)]);
+
+        let missing = GlobalIndex::new(3);
+        assert_eq!(instance.get_global(missing), None);
+        assert_eq!(instance.shadow_stack_pointer(missing), None);
+        assert!(instance.set_global(missing, 0).is_err());
+        assert!(instance.set_shadow_stack_pointer(missing, 0).is_err());
+    }
+
+    #[test]
+    fn test_passive_segment_operations() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (table 4 anyfunc)
+              (func $f)
+              (elem (i32.const 0) $f $f)
+              (data (i32.const 0) "abcd"))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let mut instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+
+        instance.memory_init(0, 0, 8, 1, 3).unwrap();
+        assert_eq!(&instance.memories()[0][8..11], b"bcd");
+        assert!(instance.memory_init(0, 0, 0, 2, 3).is_err());
+        assert!(instance.memory_init(0, 0, LinearMemory::WASM_PAGE_SIZE - 1, 0, 2).is_err());
+        assert!(instance.memory_init(0, 1, 0, 0, 1).is_err());
+        assert!(instance.memory_init(1, 0, 0, 0, 1).is_err());
+
+        // Dropped segments behave as empty ones
+        instance.drop_data_segment(0).unwrap();
+        instance.memory_init(0, 0, 0, 0, 0).unwrap();
+        assert!(instance.memory_init(0, 0, 0, 0, 1).is_err());
+        assert!(instance.drop_data_segment(1).is_err());
+        assert_eq!(&instance.memories()[0][..4], b"abcd");
+
+        let f = instance.get_function_pointer(FuncIndex::new(0)) as usize;
+        instance.table_init(0, 0, 2, 0, 2).unwrap();
+        assert_eq!(instance.table_get(0, 3), Some(f));
+        instance.drop_element_segment(0).unwrap();
+        instance.table_init(0, 0, 0, 0, 0).unwrap();
+        assert!(instance.table_init(0, 0, 0, 0, 1).is_err());
+        assert!(instance.drop_element_segment(1).is_err());
+        // The table keeps the elements initialized from the segment
+        assert_eq!(instance.table_get(0, 0), Some(f));
+    }
+
+    #[test]
+    fn test_run_exports_matching() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (func (export "test_b") (result i32) (i32.const 2))
+              (func (export "test_a") unreachable)
+              (func (export "test_param") (param i32))
+              (func (export "test_c") (result i32) (i32.const 3))
+              (func (export "other") (result i32) (i32.const 0)))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let results = instance.run_exports_matching(&module, "test_");
+
+        // In name order, skipping the functions that take parameters
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["test_a", "test_b", "test_c"]);
+        match results[0].1 {
+            Err(ErrorKind::Trap(TrapCode::IllegalInstruction, _)) => {}
+            ref result => panic!("expected a trap, got {:?}", result),
+        }
+        // The trap doesn't stop the functions after it
+        assert_eq!(results[1].1.as_ref().unwrap(), &vec![Value::I32(2)]);
+        assert_eq!(results[2].1.as_ref().unwrap(), &vec![Value::I32(3)]);
+        assert!(instance.run_exports_matching(&module, "missing_").is_empty());
+    }
+
+    #[test]
+    fn test_symbol_map() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func))
+              (func (export "named") (result i32) (i32.const 1))
+              (func (result i32) (i32.const 2)))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+
+        // Only the local functions have machine code, named after their export
+        let symbols = instance.symbol_map(&module);
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].0, "named");
+        assert_eq!(symbols[1].0, "function_2");
+        for (i, (_, address, size)) in symbols.iter().enumerate() {
+            assert_eq!(*address, instance.get_function_pointer(FuncIndex::new(i + 1)));
+            assert!(*size > 0);
+        }
+    }
+
+    #[test]
+    fn test_memory_checksum() {
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory 1)
+              (func (export "store") (param i32)
+                (i32.store8 (i32.const 100) (get_local 0))))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let instance = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let other = Instance::new(&module, ImportObject::new(), options()).unwrap();
+        let initial = instance.memory_checksum(0).unwrap();
+        assert_eq!(other.memory_checksum(0), Some(initial));
+
+        instance.execute_fn(&module, "store", &[Value::I32(1)]).unwrap();
+        assert_ne!(instance.memory_checksum(0), Some(initial));
+        other.execute_fn(&module, "store", &[Value::I32(1)]).unwrap();
+        assert_eq!(other.memory_checksum(0), instance.memory_checksum(0));
+        assert_eq!(instance.memory_checksum(1), None);
+    }
+}
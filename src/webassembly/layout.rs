@@ -0,0 +1,150 @@
+//! Marshaling of structured records (laid out like C structs) between
+//! the host and the linear memory.
+use cranelift_codegen::ir::Type;
+
+use super::errors::ErrorKind;
+use super::memory::LinearMemory;
+use super::value::Value;
+
+/// The layout of a C struct with WebAssembly typed fields: every field
+/// is aligned to its size, and the struct size is padded to the alignment
+/// of its largest field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructLayout {
+    fields: Vec<(Type, usize)>,
+    size: usize,
+    align: usize,
+}
+
+impl StructLayout {
+    /// Computes the layout of a struct with fields of the given types, in order.
+    pub fn new(field_types: &[Type]) -> Self {
+        let mut fields = Vec::with_capacity(field_types.len());
+        let mut offset = 0;
+        let mut align = 1;
+        for ty in field_types {
+            let field_size = ty.bytes() as usize;
+            offset = align_to(offset, field_size);
+            fields.push((*ty, offset));
+            offset += field_size;
+            align = align.max(field_size);
+        }
+        StructLayout {
+            fields,
+            size: align_to(offset, align),
+            align,
+        }
+    }
+
+    /// Returns the size of the struct in bytes, including the padding.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the alignment of the struct in bytes.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Returns the offset of the field `index` within the struct.
+    pub fn offset(&self, index: usize) -> Option<usize> {
+        self.fields.get(index).map(|(_, offset)| *offset)
+    }
+
+    /// Writes the fields `values` in the struct at `address` in the memory.
+    /// The padding bytes are left untouched.
+    pub fn write(
+        &self,
+        memory: &mut LinearMemory,
+        address: usize,
+        values: &[Value],
+    ) -> Result<(), ErrorKind> {
+        if values.len() != self.fields.len() {
+            return Err(ErrorKind::RuntimeError(format!(
+                "the struct has {} fields, received {} values",
+                self.fields.len(),
+                values.len()
+            )));
+        }
+        let bytes = self.struct_bytes_mut(memory, address)?;
+        for (value, (ty, offset)) in values.iter().zip(self.fields.iter()) {
+            if value.ty() != *ty {
+                return Err(ErrorKind::RuntimeError(format!(
+                    "the field at offset {} is a {}, received {:?}",
+                    offset, ty, value
+                )));
+            }
+            let bits = value.to_bits();
+            for i in 0..ty.bytes() as usize {
+                bytes[offset + i] = (bits >> (8 * i)) as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the fields of the struct at `address` in the memory.
+    pub fn read(&self, memory: &LinearMemory, address: usize) -> Result<Vec<Value>, ErrorKind> {
+        let bytes = self.struct_bytes(memory, address)?;
+        Ok(self
+            .fields
+            .iter()
+            .map(|(ty, offset)| {
+                let bits = (0..ty.bytes() as usize).fold(0u64, |bits, i| {
+                    bits | u64::from(bytes[offset + i]) << (8 * i)
+                });
+                Value::from_bits(*ty, bits).expect("struct fields are WebAssembly types")
+            })
+            .collect())
+    }
+
+    fn struct_bytes<'a>(
+        &self,
+        memory: &'a LinearMemory,
+        address: usize,
+    ) -> Result<&'a [u8], ErrorKind> {
+        address
+            .checked_add(self.size)
+            .and_then(|end| memory.get(address..end))
+            .ok_or_else(|| ErrorKind::RuntimeError("out of bounds memory access".to_string()))
+    }
+
+    fn struct_bytes_mut<'a>(
+        &self,
+        memory: &'a mut LinearMemory,
+        address: usize,
+    ) -> Result<&'a mut [u8], ErrorKind> {
+        address
+            .checked_add(self.size)
+            .and_then(move |end| memory.get_mut(address..end))
+            .ok_or_else(|| ErrorKind::RuntimeError("out of bounds memory access".to_string()))
+    }
+}
+
+fn align_to(offset: usize, align: usize) -> usize {
+    (offset + align - 1) / align * align
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::memory::LinearMemory;
+    use super::super::value::Value;
+    use super::StructLayout;
+    use cranelift_codegen::ir::types;
+
+    #[test]
+    fn test_struct_roundtrip() {
+        let layout = StructLayout::new(&[types::I32, types::F64, types::I32]);
+        assert_eq!(layout.offset(1), Some(8));
+        assert_eq!(layout.size(), 24);
+        assert_eq!(layout.align(), 8);
+
+        let mut memory = LinearMemory::new(1, None);
+        let values = [Value::I32(-1), Value::F64(1.5), Value::I32(7)];
+        layout.write(&mut memory, 16, &values).unwrap();
+        assert_eq!(&memory[16..20], &[0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(layout.read(&memory, 16).unwrap(), values.to_vec());
+        assert!(layout
+            .read(&memory, LinearMemory::WASM_PAGE_SIZE - 8)
+            .is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! Parsing of the `linking` and `reloc.*` custom sections of the relocatable
+//! object files emitted by LLVM, which describe their symbols and relocations.
+//! See https://github.com/WebAssembly/tool-conventions/blob/master/Linking.md
+use super::custom_section::{custom_sections, Reader};
+use super::errors::ErrorKind;
+
+const LINKING_SECTION_NAME: &str = "linking";
+const RELOC_SECTION_PREFIX: &str = "reloc.";
+
+const WASM_SYMBOL_TABLE: u8 = 8;
+
+const SYMTAB_FUNCTION: u8 = 0;
+const SYMTAB_DATA: u8 = 1;
+const SYMTAB_GLOBAL: u8 = 2;
+const SYMTAB_SECTION: u8 = 3;
+
+const WASM_SYM_UNDEFINED: u32 = 0x10;
+const WASM_SYM_EXPLICIT_NAME: u32 = 0x40;
+
+/// The kind of the entity a symbol refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Data,
+    Global,
+    Section,
+    /// A kind not known by the engine (e.g. events)
+    Other(u8),
+}
+
+/// A symbol of the `linking` section symbol table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolInfo {
+    pub kind: SymbolKind,
+    /// The `WASM_SYM_*` flags (binding, visibility, undefined, ...)
+    pub flags: u32,
+    /// The name of the symbol. Undefined symbols without explicit name take
+    /// the name of their import, so they have no name here.
+    pub name: Option<String>,
+    /// The index of the function, global or section the symbol refers to,
+    /// or of the data segment for defined data symbols
+    pub index: Option<u32>,
+}
+
+/// An entry of a `reloc.*` section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelocationEntry {
+    /// The `R_WASM_*` relocation type
+    pub ty: u8,
+    /// The offset of the value to relocate, from the start of the section payload
+    pub offset: u32,
+    /// The index of the symbol (or of the type, for type index relocations)
+    pub index: u32,
+    pub addend: Option<i32>,
+}
+
+/// The relocations of a section, from its `reloc.*` section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelocationSection {
+    /// The name of the relocation section (e.g. `reloc.CODE`)
+    pub name: String,
+    /// The index of the section the relocations apply to
+    pub section_index: u32,
+    pub entries: Vec<RelocationEntry>,
+}
+
+/// The linking metadata of a relocatable object file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkingInfo {
+    /// The version of the linking metadata
+    pub version: u32,
+    pub symbols: Vec<SymbolInfo>,
+    pub relocations: Vec<RelocationSection>,
+}
+
+fn has_addend(ty: u8) -> bool {
+    match ty {
+        // R_WASM_MEMORY_ADDR_LEB, R_WASM_MEMORY_ADDR_SLEB, R_WASM_MEMORY_ADDR_I32,
+        // R_WASM_FUNCTION_OFFSET_I32, R_WASM_SECTION_OFFSET_I32, R_WASM_MEMORY_ADDR_REL_SLEB
+        3 | 4 | 5 | 8 | 9 | 11 => true,
+        _ => false,
+    }
+}
+
+fn read_symbol(reader: &mut Reader) -> Result<SymbolInfo, ErrorKind> {
+    let kind = reader.read_u8()?;
+    let flags = reader.read_var_u32()?;
+    let defined = flags & WASM_SYM_UNDEFINED == 0;
+    let mut symbol = SymbolInfo {
+        kind: SymbolKind::Other(kind),
+        flags,
+        name: None,
+        index: None,
+    };
+    match kind {
+        SYMTAB_DATA => {
+            symbol.kind = SymbolKind::Data;
+            symbol.name = Some(reader.read_string()?);
+            if defined {
+                symbol.index = Some(reader.read_var_u32()?);
+                // The offset within the segment and the size of the data
+                reader.read_var_u32()?;
+                reader.read_var_u32()?;
+            }
+        }
+        SYMTAB_SECTION => {
+            symbol.kind = SymbolKind::Section;
+            symbol.index = Some(reader.read_var_u32()?);
+        }
+        _ => {
+            symbol.kind = match kind {
+                SYMTAB_FUNCTION => SymbolKind::Function,
+                SYMTAB_GLOBAL => SymbolKind::Global,
+                _ => SymbolKind::Other(kind),
+            };
+            symbol.index = Some(reader.read_var_u32()?);
+            if defined || flags & WASM_SYM_EXPLICIT_NAME != 0 {
+                symbol.name = Some(reader.read_string()?);
+            }
+        }
+    }
+    Ok(symbol)
+}
+
+fn parse_linking_section(payload: &[u8], info: &mut LinkingInfo) -> Result<(), ErrorKind> {
+    let mut reader = Reader::new(payload);
+    info.version = reader.read_var_u32()?;
+    while !reader.eof() {
+        let subsection_type = reader.read_u8()?;
+        let len = reader.read_var_u32()? as usize;
+        let mut subsection = Reader::new(reader.read_bytes(len)?);
+        // The segment info, init functions and comdats are not needed for now
+        if subsection_type == WASM_SYMBOL_TABLE {
+            let count = subsection.read_var_u32()?;
+            for _ in 0..count {
+                info.symbols.push(read_symbol(&mut subsection)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_reloc_section(name: String, payload: &[u8]) -> Result<RelocationSection, ErrorKind> {
+    let mut reader = Reader::new(payload);
+    let section_index = reader.read_var_u32()?;
+    let count = reader.read_var_u32()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let ty = reader.read_u8()?;
+        let offset = reader.read_var_u32()?;
+        let index = reader.read_var_u32()?;
+        let addend = if has_addend(ty) {
+            Some(reader.read_var_i32()?)
+        } else {
+            None
+        };
+        entries.push(RelocationEntry {
+            ty,
+            offset,
+            index,
+            addend,
+        });
+    }
+    Ok(RelocationSection {
+        name,
+        section_index,
+        entries,
+    })
+}
+
+/// Returns the contents of the `linking` and `reloc.*` sections of the
+/// WebAssembly binary, or `None` if it's not a relocatable object file.
+pub fn parse_linking_info(buffer: &[u8]) -> Result<Option<LinkingInfo>, ErrorKind> {
+    let mut info = LinkingInfo::default();
+    let mut is_object_file = false;
+    for (name, payload) in custom_sections(buffer)? {
+        if name == LINKING_SECTION_NAME {
+            is_object_file = true;
+            parse_linking_section(payload, &mut info)?;
+        } else if name.starts_with(RELOC_SECTION_PREFIX) {
+            info.relocations.push(parse_reloc_section(name, payload)?);
+        }
+    }
+    Ok(if is_object_file { Some(info) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_linking_info, RelocationEntry, SymbolInfo, SymbolKind};
+
+    #[test]
+    fn test_parse_linking() {
+        let mut buffer = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        buffer.extend_from_slice(&[
+            0x00, 0x14, // custom section, 20 bytes
+            0x07, b'l', b'i', b'n', b'k', b'i', b'n', b'g', 0x02, // version 2
+            0x08, 0x09, 0x02, // symbol table, 2 symbols
+            0x00, 0x00, 0x00, 0x01, b'f', // defined function "f"
+            0x00, 0x10, 0x01, // undefined function
+        ]);
+        buffer.extend_from_slice(&[
+            0x00, 0x10, // custom section, 16 bytes
+            0x0a, b'r', b'e', b'l', b'o', b'c', b'.', b'C', b'O', b'D', b'E',
+            0x03, 0x01, // section 3, 1 entry
+            0x00, 0x04, 0x01, // R_WASM_FUNCTION_INDEX_LEB at 4, symbol 1
+        ]);
+        let info = parse_linking_info(&buffer).unwrap().unwrap();
+        assert_eq!(info.version, 2);
+        assert_eq!(
+            info.symbols,
+            vec![
+                SymbolInfo {
+                    kind: SymbolKind::Function,
+                    flags: 0,
+                    name: Some("f".to_string()),
+                    index: Some(0),
+                },
+                SymbolInfo {
+                    kind: SymbolKind::Function,
+                    flags: 0x10,
+                    name: None,
+                    index: Some(1),
+                },
+            ]
+        );
+        assert_eq!(info.relocations[0].section_index, 3);
+        assert_eq!(
+            info.relocations[0].entries,
+            vec![RelocationEntry {
+                ty: 0,
+                offset: 4,
+                index: 1,
+                addend: None,
+            }]
+        );
+    }
+}
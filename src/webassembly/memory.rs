@@ -3,13 +3,55 @@
 //! webassembly::Instance.
 //! A memory created by Rust or in WebAssembly code will be accessible and
 //! mutable from both Rust and WebAssembly.
-use nix::sys::mman::{mmap, MapFlags, ProtFlags};
-use nix::libc::{c_void, mprotect, PROT_READ, PROT_WRITE};
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use nix::libc::{c_void, mprotect, PROT_NONE, PROT_READ, PROT_WRITE};
+#[cfg(target_os = "linux")]
+use nix::libc::{madvise, MADV_HUGEPAGE};
+use region;
+use std::mem::size_of;
+use std::ptr;
 use std::slice;
 use std::ops::{Deref, DerefMut};
 
+use super::errors::ErrorKind;
+use super::utils::zero_volatile;
+
 const PAGE_SIZE: u32 = 65536;
 const MAX_PAGES: u32 = 65536;
+#[cfg(target_os = "linux")]
+const HUGE_PAGE_SIZE: usize = 2 << 20; // 2 MiB
+
+/// The plain old data types that can be copied in bulk between the
+/// host and the linear memory, where they're stored in little-endian.
+pub unsafe trait Pod: Copy {
+    /// Converts the value between the host byte order and little-endian
+    /// (a no-op on little-endian hosts).
+    fn to_le_order(self) -> Self;
+}
+
+macro_rules! impl_pod_for_int {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {
+            fn to_le_order(self) -> Self {
+                <$ty>::to_le(self)
+            }
+        })*
+    };
+}
+
+impl_pod_for_int!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+unsafe impl Pod for f32 {
+    fn to_le_order(self) -> Self {
+        f32::from_bits(self.to_bits().to_le())
+    }
+}
+
+unsafe impl Pod for f64 {
+    fn to_le_order(self) -> Self {
+        f64::from_bits(self.to_bits().to_le())
+    }
+}
 
 /// A linear memory instance.
 //
@@ -24,6 +66,8 @@ pub struct LinearMemory {
     // request.  In general, most WebAssembly modules shouldn't need
     // to set a maximum.
     maximum: Option<u32>,
+    // Shall we zero the memory contents before unmapping it?
+    zero_on_drop: bool,
 }
 
 /// It holds the raw bytes of memory accessed by a WebAssembly Instance
@@ -37,18 +81,37 @@ impl LinearMemory {
     ///
     /// `maximum` cannot be set to more than `65536` pages.
     pub fn new(initial: u32, maximum: Option<u32>) -> Self {
+        Self::new_aligned(initial, maximum, 1)
+    }
+
+    /// Create a new linear memory instance whose base address is aligned
+    /// to `alignment` bytes, which must be a power of two.
+    ///
+    /// On Linux, the memory is advised to be backed by transparent huge pages
+    /// when it's aligned to a huge page boundary (2 MiB) or more.
+    pub fn new_aligned(initial: u32, maximum: Option<u32>, alignment: usize) -> Self {
         assert!(initial <= MAX_PAGES);
         assert!(maximum.is_none() || maximum.unwrap() <= MAX_PAGES);
+        assert!(alignment.is_power_of_two());
         debug!(
-            "Instantiate LinearMemory(initial={:?}, maximum={:?})",
-            initial, maximum
+            "Instantiate LinearMemory(initial={:?}, maximum={:?}, alignment={:?})",
+            initial, maximum, alignment
         );
 
+        // The mappings are always page aligned, so we only need to reserve
+        // extra space (and unmap what's left of it) for bigger alignments.
+        let page_size = region::page::size();
+        let extra = if alignment > page_size {
+            alignment - page_size
+        } else {
+            0
+        };
+
         // TODO: Investigate if memory is zeroed out
-        let base = unsafe {
+        let reserved = unsafe {
             mmap(
                 0 as _,
-                LinearMemory::DEFAULT_SIZE,
+                LinearMemory::DEFAULT_SIZE + extra,
                 ProtFlags::PROT_NONE,
                 MapFlags::MAP_ANON | MapFlags::MAP_SHARED,
                 -1,
@@ -56,6 +119,33 @@ impl LinearMemory {
             ).unwrap()
         };
 
+        let base = if extra > 0 {
+            let start = reserved as usize;
+            let aligned = (start + alignment - 1) & !(alignment - 1);
+            let before = aligned - start;
+            let after = extra - before;
+            unsafe {
+                if before > 0 {
+                    munmap(reserved, before).expect("failed to unmap the alignment padding");
+                }
+                if after > 0 {
+                    munmap((aligned + LinearMemory::DEFAULT_SIZE) as *mut c_void, after)
+                        .expect("failed to unmap the alignment padding");
+                }
+            }
+            aligned as *mut c_void
+        } else {
+            reserved
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            if alignment >= HUGE_PAGE_SIZE {
+                // It's only a hint, so we don't mind if it fails
+                unsafe { madvise(base, LinearMemory::DEFAULT_SIZE, MADV_HUGEPAGE) };
+            }
+        }
+
         if initial > 0 {
             assert_eq!(unsafe {
                 mprotect(
@@ -71,6 +161,7 @@ impl LinearMemory {
             base,
             current: initial,
             maximum,
+            zero_on_drop: false,
         }
     }
 
@@ -137,6 +228,116 @@ impl LinearMemory {
         Some(prev_pages as i32)
     }
 
+    /// Shrinks the memory back to `pages` pages, e.g. for undoing a `grow`.
+    /// The released pages are zeroed and made inaccessible again.
+    pub fn shrink_to(&mut self, pages: u32) {
+        assert!(pages <= self.current);
+        let new_bytes = (pages * PAGE_SIZE) as usize;
+        let old_bytes = self.current_size();
+        unsafe {
+            ptr::write_bytes((self.base as *mut u8).add(new_bytes), 0, old_bytes - new_bytes);
+            assert_eq!(mprotect(
+                self.base.add(new_bytes),
+                old_bytes - new_bytes,
+                PROT_NONE,
+            ), 0);
+        }
+        self.current = pages;
+    }
+
+    /// Sets whether the memory contents will be zeroed (with writes that
+    /// can't be optimized away) before the memory is unmapped on drop.
+    pub fn set_zero_on_drop(&mut self, zero_on_drop: bool) {
+        self.zero_on_drop = zero_on_drop;
+    }
+
+    /// Returns whether the memory contents will be zeroed on drop.
+    pub fn zero_on_drop(&self) -> bool {
+        self.zero_on_drop
+    }
+
+    /// Zeroes the accessible pages if `zero_on_drop` is set. The mapping
+    /// stays valid, so this is split from `drop` for testing.
+    fn zero_if_requested(&mut self) {
+        if self.zero_on_drop {
+            zero_volatile(&mut self[..]);
+        }
+    }
+
+    /// Checks that `offset..offset + len` is within the accessible memory.
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<(), ErrorKind> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.current_size() => Ok(()),
+            _ => Err(ErrorKind::RuntimeError(
+                "out of bounds memory access".to_string(),
+            )),
+        }
+    }
+
+    /// Copies `len` bytes from `src` into `dst` within this memory.
+    /// The ranges may overlap.
+    pub fn copy_within(&mut self, dst: usize, src: usize, len: usize) -> Result<(), ErrorKind> {
+        self.check_bounds(src, len)?;
+        self.check_bounds(dst, len)?;
+        unsafe {
+            let base = self.base as *mut u8;
+            ptr::copy(base.add(src), base.add(dst), len);
+        }
+        Ok(())
+    }
+
+    /// Copies `len` bytes from `src` in `src_memory` into `dst` in this memory.
+    pub fn copy_from(
+        &mut self,
+        dst: usize,
+        src_memory: &LinearMemory,
+        src: usize,
+        len: usize,
+    ) -> Result<(), ErrorKind> {
+        src_memory.check_bounds(src, len)?;
+        self.check_bounds(dst, len)?;
+        self[dst..dst + len].copy_from_slice(&src_memory[src..src + len]);
+        Ok(())
+    }
+
+    /// Writes `values` as little-endian at `offset`, with a single bounds check.
+    /// The offset doesn't need to be aligned.
+    pub fn write_slice_le<T: Pod>(&mut self, offset: usize, values: &[T]) -> Result<(), ErrorKind> {
+        let len = Self::slice_len::<T>(values.len())?;
+        self.check_bounds(offset, len)?;
+        let dst = unsafe { (self.base as *mut u8).add(offset) };
+        if cfg!(target_endian = "little") {
+            unsafe { ptr::copy_nonoverlapping(values.as_ptr() as *const u8, dst, len) };
+        } else {
+            for (i, value) in values.iter().enumerate() {
+                unsafe { ptr::write_unaligned((dst as *mut T).add(i), value.to_le_order()) };
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `values.len()` little-endian values at `offset` into `values`,
+    /// with a single bounds check. The offset doesn't need to be aligned.
+    pub fn read_slice_le<T: Pod>(&self, offset: usize, values: &mut [T]) -> Result<(), ErrorKind> {
+        let len = Self::slice_len::<T>(values.len())?;
+        self.check_bounds(offset, len)?;
+        let src = unsafe { (self.base as *const u8).add(offset) };
+        if cfg!(target_endian = "little") {
+            unsafe { ptr::copy_nonoverlapping(src, values.as_mut_ptr() as *mut u8, len) };
+        } else {
+            for (i, value) in values.iter_mut().enumerate() {
+                *value = unsafe { ptr::read_unaligned((src as *const T).add(i)) }.to_le_order();
+            }
+        }
+        Ok(())
+    }
+
+    fn slice_len<T>(count: usize) -> Result<usize, ErrorKind> {
+        count.checked_mul(size_of::<T>()).ok_or_else(|| {
+            ErrorKind::RuntimeError("out of bounds memory access".to_string())
+        })
+    }
+
     pub fn carve_slice(&self, offset: u32, size: u32) -> Option<&[u8]> {
         let start = offset as usize;
         let end = start + size as usize;
@@ -157,6 +358,15 @@ impl PartialEq for LinearMemory {
     }
 }
 
+impl Drop for LinearMemory {
+    fn drop(&mut self) {
+        self.zero_if_requested();
+        // The whole reservation (including the guard pages) is released
+        unsafe {
+            munmap(self.base, LinearMemory::DEFAULT_SIZE).expect("failed to unmap the linear memory");
+        }
+    }
+}
 
 impl Deref for LinearMemory {
     type Target = [u8];
@@ -173,4 +383,79 @@ impl DerefMut for LinearMemory {
             slice::from_raw_parts_mut(self.base as _, (self.current * PAGE_SIZE) as _)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinearMemory;
+
+    #[test]
+    fn test_slice_le_roundtrip() {
+        let mut memory = LinearMemory::new(1, None);
+        memory.write_slice_le(3, &[1.5f32, -2.0]).unwrap();
+        assert_eq!(&memory[3..7], &[0x00, 0x00, 0xc0, 0x3f]);
+        let mut values = [0f32; 2];
+        memory.read_slice_le(3, &mut values).unwrap();
+        assert_eq!(values, [1.5, -2.0]);
+
+        let mut values = [0u64; 2];
+        assert!(memory
+            .read_slice_le(LinearMemory::WASM_PAGE_SIZE - 8, &mut values)
+            .is_err());
+    }
+
+    #[test]
+    fn test_copy_between_memories() {
+        let mut src = LinearMemory::new(1, None);
+        let mut dst = LinearMemory::new(2, None);
+        src[10..14].copy_from_slice(&[1, 2, 3, 4]);
+        dst.copy_from(LinearMemory::WASM_PAGE_SIZE + 5, &src, 10, 4)
+            .unwrap();
+        let offset = LinearMemory::WASM_PAGE_SIZE + 5;
+        assert_eq!(&dst[offset..offset + 4], &[1, 2, 3, 4]);
+        // The source memory is left untouched
+        assert_eq!(&src[10..14], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_copy_between_memories_out_of_bounds() {
+        let src = LinearMemory::new(1, None);
+        let mut dst = LinearMemory::new(2, None);
+        // The destination range fits, but the source one doesn't
+        assert!(dst
+            .copy_from(0, &src, LinearMemory::WASM_PAGE_SIZE - 2, 4)
+            .is_err());
+        assert!(dst
+            .copy_from(2 * LinearMemory::WASM_PAGE_SIZE - 2, &src, 0, 4)
+            .is_err());
+    }
+
+    #[test]
+    fn test_copy_within_overlapping() {
+        let mut memory = LinearMemory::new(1, None);
+        memory[0..4].copy_from_slice(&[1, 2, 3, 4]);
+        memory.copy_within(2, 0, 4).unwrap();
+        assert_eq!(&memory[0..6], &[1, 2, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_new_aligned() {
+        let alignment = 1 << 21;
+        let mut memory = LinearMemory::new_aligned(1, None, alignment);
+        assert_eq!(memory.base_addr() as usize % alignment, 0);
+        memory[LinearMemory::WASM_PAGE_SIZE - 1] = 1;
+    }
+
+    #[test]
+    fn test_zero_if_requested() {
+        let mut memory = LinearMemory::new(2, None);
+        memory[10] = 1;
+        memory[LinearMemory::WASM_PAGE_SIZE + 10] = 2;
+        memory.zero_if_requested();
+        assert_eq!(memory[10], 1);
+
+        memory.set_zero_on_drop(true);
+        memory.zero_if_requested();
+        assert!(memory.iter().all(|byte| *byte == 0));
+    }
+}
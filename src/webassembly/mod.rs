@@ -1,24 +1,50 @@
+pub mod custom_section;
+pub mod dylink;
+pub mod encoder;
 pub mod errors;
+pub mod float_env;
 pub mod import_object;
 pub mod instance;
+pub mod layout;
+pub mod linking;
 pub mod math_intrinsics;
 pub mod memory;
 pub mod module;
+pub mod pool;
+pub mod profile;
+pub mod proposals;
 pub mod relocation;
+pub mod shadow;
+pub mod trap;
 pub mod utils;
+pub mod value;
 
 use cranelift_codegen::{isa, settings::{self, Configurable}};
 use std::panic;
+use std::path::Path;
 use std::str::FromStr;
 use target_lexicon;
 use wasmparser;
 use wasmparser::WasmDecoder;
 
+pub use self::dylink::DylinkInfo;
 pub use self::errors::{Error, ErrorKind};
-pub use self::import_object::{ImportObject, ImportValue};
-pub use self::instance::{Instance, InstanceOptions};
-pub use self::memory::LinearMemory;
-pub use self::module::{Export, Module, ModuleInfo};
+pub use self::import_object::{
+    ImportDescriptor, ImportKind, ImportObject, ImportObjectTemplate, ImportValue,
+};
+pub use self::instance::{
+    BoundFunction, CodePlacement, CostTable, FootprintBreakdown, FrameInfo, Instance,
+    InstanceOptions, OutParam,
+};
+pub use self::layout::StructLayout;
+pub use self::linking::LinkingInfo;
+pub use self::memory::{LinearMemory, Pod};
+pub use self::module::{Export, Module, ModuleInfo, ModuleOptions};
+pub use self::pool::{InstancePool, PooledInstance};
+pub use self::profile::{ProfileNode, ProfileTree};
+pub use self::proposals::ProposalSet;
+pub use self::trap::{set_trap_message_formatter, TrapCode};
+pub use self::value::Value;
 
 pub struct ResultObject {
     /// A webassembly::Module object representing the compiled WebAssembly module.
@@ -61,16 +87,7 @@ pub fn instantiate(
 
     let module = compile(buffer_source)?;
     debug!("webassembly - creating instance");
-    let instance = Instance::new(
-        &module,
-        import_object,
-        InstanceOptions {
-            mock_missing_imports: true,
-            mock_missing_globals: true,
-            mock_missing_tables: true,
-            isa: isa,
-        },
-    )?;
+    let instance = Instance::new(&module, import_object, InstanceOptions::new(isa, true))?;
     debug!("webassembly - instance created");
     Ok(ResultObject { module, instance })
 }
@@ -96,20 +113,56 @@ pub fn instantiate_streaming(
 /// If the operation fails, the Result rejects with a
 /// webassembly::CompileError.
 pub fn compile(buffer_source: Vec<u8>) -> Result<Module, ErrorKind> {
-    // TODO: This should be automatically validated when creating the Module
-    debug!("webassembly - validating module");
-    validate_or_error(&buffer_source)?;
+    compile_with_options(buffer_source, ModuleOptions::default())
+}
 
+/// Same as webassembly::compile(), but allows customizing how the
+/// module is translated through the provided `ModuleOptions`.
+pub fn compile_with_options(
+    buffer_source: Vec<u8>,
+    options: ModuleOptions,
+) -> Result<Module, ErrorKind> {
+    compile_for_target(buffer_source, "x86_64", options)
+}
+
+/// Same as webassembly::compile_with_options(), but translates the module
+/// for the provided `target` triple (e.g. `x86_64` or `aarch64-unknown-linux-gnu`)
+/// rather than for the host. The resulting module can only be instantiated
+/// with an ISA for the same architecture.
+pub fn compile_for_target(
+    buffer_source: Vec<u8>,
+    target: &str,
+    options: ModuleOptions,
+) -> Result<Module, ErrorKind> {
+    let triple = target_lexicon::Triple::from_str(target)
+        .map_err(|_| ErrorKind::CompileError(format!("Invalid target triple: {}", target)))?;
     let flags = settings::Flags::new(settings::builder());
-    let isa = isa::lookup(triple!("x86_64")).unwrap().finish(flags);
+    let isa = isa::lookup(triple.clone())
+        .map_err(|_| ErrorKind::CompileError(format!("Unsupported target: {}", target)))?
+        .finish(flags);
 
     debug!("webassembly - creating module");
-    let module = Module::from_bytes(buffer_source, isa.frontend_config())?;
+    let mut module = Module::from_bytes_with_options(buffer_source, isa.frontend_config(), options)?;
+    module.info.triple = Some(triple);
     debug!("webassembly - module created");
 
     Ok(module)
 }
 
+/// Same as webassembly::compile(), but compiles the .wasm file at `path`,
+/// which is mapped in memory rather than read into a buffer.
+pub fn compile_file(path: &Path) -> Result<Module, ErrorKind> {
+    let triple = triple!("x86_64");
+    let isa = isa::lookup(triple.clone())
+        .map_err(|_| ErrorKind::CompileError("Unsupported target: x86_64".to_string()))?
+        .finish(settings::Flags::new(settings::builder()));
+
+    debug!("webassembly - creating module from {}", path.display());
+    let mut module = Module::from_file(path, isa.frontend_config(), ModuleOptions::default())?;
+    module.info.triple = Some(triple);
+    Ok(module)
+}
+
 /// The webassembly::validate() function validates a given typed
 /// array of WebAssembly binary code, returning whether the bytes
 /// form a valid wasm module (true) or not (false).
@@ -127,12 +180,64 @@ pub fn validate_or_error(bytes: &[u8]) -> Result<(), ErrorKind> {
         match *state {
             wasmparser::ParserState::EndWasm => return Ok(()),
             wasmparser::ParserState::Error(err) => {
+                if accesses_missing_memory(bytes) {
+                    return Err(ErrorKind::NoMemory);
+                }
                 return Err(ErrorKind::CompileError(format!(
                     "Validation error: {}",
                     err.message
-                )))
+                )));
             }
             _ => (),
         }
     }
 }
+
+/// Returns true if a function of the module accesses a memory, while the
+/// module neither defines nor imports one. It's only used for reporting
+/// why an invalid module was rejected, so it doesn't validate anything.
+fn accesses_missing_memory(bytes: &[u8]) -> bool {
+    use wasmparser::{ImportSectionEntryType, Operator, ParserState};
+
+    let mut parser = wasmparser::Parser::new(bytes);
+    loop {
+        match *parser.read() {
+            ParserState::ImportSectionEntry {
+                ty: ImportSectionEntryType::Memory(_),
+                ..
+            }
+            | ParserState::MemorySectionEntry(_)
+            | ParserState::EndWasm
+            | ParserState::Error(_) => return false,
+            ParserState::CodeOperator(ref operator) => match *operator {
+                Operator::I32Load { .. }
+                | Operator::I64Load { .. }
+                | Operator::F32Load { .. }
+                | Operator::F64Load { .. }
+                | Operator::I32Load8S { .. }
+                | Operator::I32Load8U { .. }
+                | Operator::I32Load16S { .. }
+                | Operator::I32Load16U { .. }
+                | Operator::I64Load8S { .. }
+                | Operator::I64Load8U { .. }
+                | Operator::I64Load16S { .. }
+                | Operator::I64Load16U { .. }
+                | Operator::I64Load32S { .. }
+                | Operator::I64Load32U { .. }
+                | Operator::I32Store { .. }
+                | Operator::I64Store { .. }
+                | Operator::F32Store { .. }
+                | Operator::F64Store { .. }
+                | Operator::I32Store8 { .. }
+                | Operator::I32Store16 { .. }
+                | Operator::I64Store8 { .. }
+                | Operator::I64Store16 { .. }
+                | Operator::I64Store32 { .. }
+                | Operator::CurrentMemory { .. }
+                | Operator::GrowMemory { .. } => return true,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
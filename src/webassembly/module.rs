@@ -1,7 +1,11 @@
 //! Implementations of `ModuleEnvironment` (as `Module`) and `FuncEnvironment`
 //! This module helps to translate wasm code into Cranelift Codegen IR
 //! (that will be later compiled in the WebAssembly instance)
+use memmap::Mmap;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
 use std::string::String;
 use std::vec::Vec;
 
@@ -13,6 +17,7 @@ use cranelift_codegen::ir::{
 };
 use cranelift_codegen::isa::{CallConv, TargetFrontendConfig};
 use cranelift_entity::{EntityRef, PrimaryMap};
+use target_lexicon::Triple;
 
 use cranelift_wasm::{
     translate_module, DefinedFuncIndex, FuncEnvironment as FuncEnvironmentTrait, FuncIndex,
@@ -20,9 +25,15 @@ use cranelift_wasm::{
     ReturnMode, SignatureIndex, Table, TableIndex, WasmResult,
 };
 
+use super::dylink::{parse_dylink_info, DylinkInfo};
+use super::import_object::{ImportDescriptor, ImportKind, ImportObjectTemplate};
+use super::linking::{parse_linking_info, LinkingInfo};
+use super::encoder::encode_module;
 use super::errors::ErrorKind;
 use super::instance::Instance;
 use super::memory::LinearMemory;
+use super::proposals::{used_proposals, ProposalSet};
+use super::validate_or_error;
 
 /// Get the integer type used for representing pointers on this platform.
 fn native_pointer_type() -> ir::Type {
@@ -94,6 +105,17 @@ pub enum Export {
     Global(GlobalIndex),
 }
 
+/// Options that change how the WebAssembly code is translated
+/// into Cranelift IR.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleOptions {
+    /// Emit an explicit bounds check on every memory access instead of
+    /// relying on guard pages. Out of bounds accesses will trap at the exact
+    /// instruction that caused them, at the expense of performance.
+    /// Useful for debugging a suspected miscompiled memory access.
+    pub force_explicit_bounds_checks: bool,
+}
+
 /// TODO: Need to get rid of unused field
 /// The main state belonging to a `Module`. This is split out from
 /// `Module` to allow it to be borrowed separately from the
@@ -102,6 +124,18 @@ pub struct ModuleInfo {
     /// Target description relevant to frontends producing Cranelift IR.
     config: TargetFrontendConfig,
 
+    /// The options used to translate the module.
+    pub options: ModuleOptions,
+
+    /// The target the module was translated for, if known.
+    pub triple: Option<Triple>,
+
+    /// The requirements declared in the `dylink.0` section, if it's a side module
+    pub dylink_info: Option<DylinkInfo>,
+    /// The symbols and relocations of the `linking` and `reloc.*` sections,
+    /// if it's a relocatable object file
+    pub linking_info: Option<LinkingInfo>,
+
     pub main_memory_base: Option<ir::GlobalValue>,
 
     /// The Cranelift global holding the base address of the memories vector.
@@ -148,6 +182,9 @@ pub struct ModuleInfo {
     /// rather than iterating through the ImportableExportable elements.
     pub exports: HashMap<String, Export>,
 
+    /// The names of the exports, in declaration order
+    pub export_order: Vec<String>,
+
     /// The external function declaration for implementing wasm's `current_memory`.
     pub current_memory_extfunc: Option<FuncRef>,
 
@@ -157,9 +194,13 @@ pub struct ModuleInfo {
 
 impl ModuleInfo {
     /// Allocates the data structures with the given flags.
-    pub fn new(config: TargetFrontendConfig) -> Self {
+    pub fn new(config: TargetFrontendConfig, options: ModuleOptions) -> Self {
         Self {
             config,
+            options,
+            triple: None,
+            dylink_info: None,
+            linking_info: None,
             signatures: Vec::new(),
             functions: PrimaryMap::new(),
             function_bodies: PrimaryMap::new(),
@@ -175,6 +216,7 @@ impl ModuleInfo {
             main_memory_base: None,
             memory_base: None,
             exports: HashMap::new(),
+            export_order: Vec::new(),
             current_memory_extfunc: None,
             grow_memory_extfunc: None,
         }
@@ -228,6 +270,9 @@ pub struct Module {
 
     /// Vector of wasm bytecode size for each function.
     pub func_bytecode_sizes: Vec<usize>,
+
+    /// The wasm bytecode of each function body (locals included).
+    pub func_bytecode: Vec<Vec<u8>>,
     // How to return from functions.
     // return_mode: ReturnMode,
 }
@@ -237,18 +282,69 @@ impl Module {
     pub fn from_bytes(
         buffer_source: Vec<u8>,
         config: TargetFrontendConfig,
+    ) -> Result<Self, ErrorKind> {
+        Self::from_bytes_with_options(buffer_source, config, ModuleOptions::default())
+    }
+
+    /// Instantiate a Module given WASM bytecode and the options
+    /// used to translate it.
+    /// The bytecode is validated first, so invalid modules are rejected with a
+    /// `CompileError` rather than being translated (or `NoMemory` for the ones
+    /// that access a memory without defining or importing one).
+    pub fn from_bytes_with_options(
+        buffer_source: Vec<u8>,
+        config: TargetFrontendConfig,
+        options: ModuleOptions,
+    ) -> Result<Self, ErrorKind> {
+        Self::from_slice_with_options(&buffer_source, config, options)
+    }
+
+    /// Instantiate a Module from the WebAssembly file at `path`. The file is
+    /// mapped in memory and parsed in place rather than read into a buffer.
+    /// The module copies the parts of the binary it keeps (like the function
+    /// bodies), so the mapping is released once the module is created.
+    /// Failing to open or map the file is reported as an `IoError`.
+    pub fn from_file(
+        path: &Path,
+        config: TargetFrontendConfig,
+        options: ModuleOptions,
+    ) -> Result<Self, ErrorKind> {
+        let io_error = |action: &str, e: io::Error| {
+            ErrorKind::IoError(
+                path.display().to_string(),
+                e.kind(),
+                format!("can't {} the file: {}", action, e),
+            )
+        };
+        let file = File::open(path).map_err(|e| io_error("open", e))?;
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|e| io_error("map", e))?;
+        Self::from_slice_with_options(&mmap, config, options)
+    }
+
+    /// Same as `from_bytes_with_options`, but borrowing the WASM bytecode.
+    pub fn from_slice_with_options(
+        buffer_source: &[u8],
+        config: TargetFrontendConfig,
+        options: ModuleOptions,
     ) -> Result<Self, ErrorKind> {
         // let return_mode = ReturnMode::NormalReturns;
         let mut module = Self {
-            info: ModuleInfo::new(config),
+            info: ModuleInfo::new(config, options),
             trans: FuncTranslator::new(),
             func_bytecode_sizes: Vec::new(),
+            func_bytecode: Vec::new(),
             // return_mode,
         };
 
+        debug!("webassembly - validating module");
+        validate_or_error(buffer_source)?;
+
         // We iterate through the source bytes, generating the compiled module
-        translate_module(&buffer_source, &mut module)
+        translate_module(buffer_source, &mut module)
             .map_err(|e| ErrorKind::CompileError(e.to_string()))?;
+        module.info.dylink_info = parse_dylink_info(buffer_source)?;
+        module.info.linking_info = parse_linking_info(buffer_source)?;
+        module.validate()?;
 
         Ok(module)
     }
@@ -280,6 +376,216 @@ impl Module {
         }
     }
 
+    /// Returns the name and signature of every exported function, sorted by name.
+    /// Useful for automatically invoking the exports (e.g. when fuzzing),
+    /// as it tells the number and type of the arguments each function expects.
+    pub fn exported_functions_with_types(&self) -> Vec<(String, ir::Signature)> {
+        let mut functions: Vec<(String, ir::Signature)> = self
+            .info
+            .exports
+            .iter()
+            .filter_map(|(name, export)| match export {
+                Export::Function(func_index) => {
+                    let sig_index = self.info.functions[*func_index].entity;
+                    Some((name.clone(), self.info.signatures[sig_index.index()].clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        functions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        functions
+    }
+
+    /// Returns the name and signature of every exported function, in
+    /// declaration order.
+    pub fn exported_functions_in_order(&self) -> Vec<(String, ir::Signature)> {
+        self.info
+            .export_order
+            .iter()
+            .filter_map(|name| match self.info.exports.get(name) {
+                Some(Export::Function(func_index)) => {
+                    let sig_index = self.info.functions[*func_index].entity;
+                    Some((name.clone(), self.info.signatures[sig_index.index()].clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Encodes the module back to the WebAssembly binary format.
+    /// The custom sections (like the names) are not kept.
+    pub fn to_binary(&self) -> Result<Vec<u8>, ErrorKind> {
+        encode_module(self)
+    }
+
+    /// Returns the post-MVP proposals that the module uses.
+    pub fn used_proposals(&self) -> ProposalSet {
+        used_proposals(self)
+    }
+
+    /// Returns the size in bytes of the body of each local function
+    /// (indexed by `DefinedFuncIndex`), to get an idea of the module shape
+    /// and of how costly it will be to compile.
+    pub fn function_size_histogram(&self) -> Vec<usize> {
+        self.func_bytecode_sizes.clone()
+    }
+
+    /// Returns the function index (imports included) and the raw WebAssembly
+    /// bytecode (locals declarations included) of each local function,
+    /// for analyzing the bodies without parsing the whole binary again.
+    pub fn function_bodies(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        let imported_funcs = self.info.imported_funcs.len();
+        self.func_bytecode
+            .iter()
+            .enumerate()
+            .map(move |(i, body)| (imported_funcs + i, &body[..]))
+    }
+
+    /// Returns the declared limits of each memory (imported or local),
+    /// as the minimum number of pages and the optional maximum number of pages.
+    pub fn memory_limits(&self) -> Vec<(u32, Option<u32>)> {
+        self.info
+            .memories
+            .iter()
+            .map(|memory| {
+                let memory = memory.entity;
+                (memory.pages_count as u32, memory.maximum.map(|m| m as u32))
+            })
+            .collect()
+    }
+
+    /// Returns the declared limits of each table (imported or local),
+    /// as the minimum number of elements and the optional maximum number of elements.
+    pub fn table_limits(&self) -> Vec<(u32, Option<u32>)> {
+        self.info
+            .tables
+            .iter()
+            .map(|table| {
+                let table = &table.entity;
+                (table.size as u32, table.maximum.map(|m| m as u32))
+            })
+            .collect()
+    }
+
+    /// Returns the declared maximum number of elements of the table `table_index`,
+    /// or `None` if the table has no maximum or doesn't exist.
+    pub fn table_maximum(&self, table_index: usize) -> Option<u32> {
+        self.info
+            .tables
+            .get(table_index)
+            .and_then(|table| table.entity.maximum.map(|m| m as u32))
+    }
+
+    /// Returns the index of the global that holds the shadow stack pointer,
+    /// looking for an exported or imported global named `name`.
+    /// Emscripten modules import it as `STACKTOP`, while LLVM names it `__stack_pointer`.
+    pub fn stack_pointer_global(&self, name: &str) -> Option<GlobalIndex> {
+        if let Some(&Export::Global(index)) = self.info.exports.get(name) {
+            return Some(index);
+        }
+        self.info
+            .globals
+            .iter()
+            .position(|global| match global.import_name {
+                Some((_, ref field)) => field == name,
+                None => false,
+            })
+            .map(GlobalIndex::new)
+    }
+
+    /// Returns the Cranelift IR of the local function `func_index` as text, as it's
+    /// handed to the code generator (before the optional overflow checks of the
+    /// instance are inserted). Returns `None` for imported functions.
+    #[cfg(feature = "debug")]
+    pub fn clif_ir(&self, func_index: FuncIndex) -> Option<String> {
+        let defined_func = self.defined_func_index(func_index)?;
+        self.info
+            .function_bodies
+            .get(defined_func)
+            .map(|func| func.to_string())
+    }
+
+    /// Returns a skeleton of the import object with every import the module
+    /// requires, so the host only has to fill in the values.
+    pub fn import_template(&self) -> ImportObjectTemplate {
+        let info = &self.info;
+        let mut imports = Vec::new();
+        for (func, (module, field)) in info.functions.values().zip(&info.imported_funcs) {
+            imports.push(ImportDescriptor {
+                module: module.clone(),
+                field: field.clone(),
+                kind: ImportKind::Function(info.signatures[func.entity.index()].clone()),
+            });
+        }
+        for table in &info.tables {
+            if let Some((ref module, ref field)) = table.import_name {
+                imports.push(ImportDescriptor {
+                    module: module.clone(),
+                    field: field.clone(),
+                    kind: ImportKind::Table {
+                        minimum: table.entity.size,
+                        maximum: table.entity.maximum,
+                    },
+                });
+            }
+        }
+        for memory in &info.memories {
+            if let Some((ref module, ref field)) = memory.import_name {
+                imports.push(ImportDescriptor {
+                    module: module.clone(),
+                    field: field.clone(),
+                    kind: ImportKind::Memory {
+                        minimum: memory.entity.pages_count,
+                        maximum: memory.entity.maximum,
+                    },
+                });
+            }
+        }
+        for global in &info.globals {
+            if let Some((ref module, ref field)) = global.import_name {
+                imports.push(ImportDescriptor {
+                    module: module.clone(),
+                    field: field.clone(),
+                    kind: ImportKind::Global {
+                        ty: global.entity.ty,
+                        mutable: global.entity.mutability,
+                    },
+                });
+            }
+        }
+        ImportObjectTemplate::new(imports)
+    }
+
+    /// Returns a human readable name for a function: its first export name,
+    /// `module.field` for imported functions, or `function_<index>` otherwise.
+    pub fn function_name(&self, func_index: FuncIndex) -> String {
+        if let Some(name) = self.info.functions[func_index].export_names.first() {
+            return name.clone();
+        }
+        match self.info.imported_funcs.get(func_index.index()) {
+            Some((module, field)) => format!("{}.{}", module, field),
+            None => format!("function_{}", func_index.index()),
+        }
+    }
+
+    /// Checks that every export points to an existing function, table,
+    /// memory or global, so resolving them can't go out of bounds.
+    pub fn validate(&self) -> Result<(), ErrorKind> {
+        let info = &self.info;
+        for (name, export) in &info.exports {
+            let (index, count) = match export {
+                Export::Function(index) => (index.index(), info.functions.len()),
+                Export::Table(index) => (index.index(), info.tables.len()),
+                Export::Memory(index) => (index.index(), info.memories.len()),
+                Export::Global(index) => (index.index(), info.globals.len()),
+            };
+            if index >= count {
+                return Err(ErrorKind::ExportIndexOutOfRange(name.clone(), index));
+            }
+        }
+        Ok(())
+    }
+
     pub fn verify(&self) {
         unimplemented!();
         // let isa = isa::lookup(self.info.triple.clone())
@@ -431,16 +737,37 @@ impl<'environment> FuncEnvironmentTrait for FuncEnvironment<'environment> {
             readonly: true,
         });
 
-        // Create table based on the data above
-        let heap = func.create_heap(ir::HeapData {
-            base: heap_base,
-            min_size: 0.into(),
-            guard_size: (LinearMemory::DEFAULT_GUARD_SIZE as i64).into(),
-            style: ir::HeapStyle::Static {
-                bound: (LinearMemory::DEFAULT_HEAP_SIZE as i64).into(),
-            },
-            index_type: I32,
-        });
+        let heap = if self.mod_info.options.force_explicit_bounds_checks {
+            // Load value at the (base + memory_data_offset + ptr_size)
+            // which is the current length of data_pointer.memories[index]
+            let bound_gv = func.create_global_value(ir::GlobalValueData::Load {
+                base,
+                offset: Offset32::new(memory_data_offset + ptr_size),
+                global_type: self.pointer_type(),
+                readonly: false,
+            });
+
+            // Create a heap without guard pages, so every access gets
+            // checked against the current memory length
+            func.create_heap(ir::HeapData {
+                base: heap_base,
+                min_size: 0.into(),
+                guard_size: 0.into(),
+                style: ir::HeapStyle::Dynamic { bound_gv },
+                index_type: I32,
+            })
+        } else {
+            // Create table based on the data above
+            func.create_heap(ir::HeapData {
+                base: heap_base,
+                min_size: 0.into(),
+                guard_size: (LinearMemory::DEFAULT_GUARD_SIZE as i64).into(),
+                style: ir::HeapStyle::Static {
+                    bound: (LinearMemory::DEFAULT_HEAP_SIZE as i64).into(),
+                },
+                index_type: I32,
+            })
+        };
 
         heap
     }
@@ -560,9 +887,6 @@ impl<'environment> FuncEnvironmentTrait for FuncEnvironment<'environment> {
             .special_param(ir::ArgumentPurpose::VMContext)
             .expect("Missing vmctx parameter");
 
-        // println!("POINTER BYTES {}", self.pointer_bytes());
-        // println!("POINTER SIZE {}", native_pointer_size());
-
         // Build a value list for the call instruction containing the call_args and the vmctx
         // parameter.
         let mut args = ir::ValueList::default();
@@ -804,6 +1128,7 @@ impl<'data> ModuleEnvironment<'data> for Module {
             .export_names
             .push(String::from(name));
         // We add to the exports to have O(1) retrieval
+        self.info.export_order.push(name.to_string());
         self.info
             .exports
             .insert(name.to_string(), Export::Function(func_index));
@@ -814,6 +1139,7 @@ impl<'data> ModuleEnvironment<'data> for Module {
             .export_names
             .push(String::from(name));
         // We add to the exports to have O(1) retrieval
+        self.info.export_order.push(name.to_string());
         self.info
             .exports
             .insert(name.to_string(), Export::Table(table_index));
@@ -824,6 +1150,7 @@ impl<'data> ModuleEnvironment<'data> for Module {
             .export_names
             .push(String::from(name));
         // We add to the exports to have O(1) retrieval
+        self.info.export_order.push(name.to_string());
         self.info
             .exports
             .insert(name.to_string(), Export::Memory(memory_index));
@@ -834,6 +1161,7 @@ impl<'data> ModuleEnvironment<'data> for Module {
             .export_names
             .push(String::from(name));
         // We add to the exports to have O(1) retrieval
+        self.info.export_order.push(name.to_string());
         self.info
             .exports
             .insert(name.to_string(), Export::Global(global_index));
@@ -857,7 +1185,167 @@ impl<'data> ModuleEnvironment<'data> for Module {
             func
         };
         self.func_bytecode_sizes.push(body_bytes.len());
+        self.func_bytecode.push(body_bytes.to_vec());
         self.info.function_bodies.push(func);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::compile;
+
+    #[test]
+    fn test_memory_access_without_memory() {
+        use super::super::errors::ErrorKind;
+
+        // (module (func (result i32) i32.const 0 i32.load))
+        // It can't be built with wat2wasm, as it validates the module.
+        let wasm = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type section
+            0x03, 0x02, 0x01, 0x00, // function section
+            0x0a, 0x09, 0x01, 0x07, 0x00, 0x41, 0x00, 0x28, 0x02, 0x00, 0x0b, // code section
+        ];
+        match compile(wasm) {
+            Err(ErrorKind::NoMemory) => {}
+            other => panic!("Expected a NoMemory error, received {:?}", other.err()),
+        }
+        // Other validation errors are still compilation errors
+        let wasm = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type section
+            0x03, 0x02, 0x01, 0x00, // function section
+            0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code section, without the result
+        ];
+        match compile(wasm) {
+            Err(ErrorKind::CompileError(_)) => {}
+            other => panic!("Expected a CompileError, received {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_compile_file() {
+        use super::super::compile_file;
+        use super::super::errors::ErrorKind;
+        use std::{env, fs, io, process};
+        use wabt::wat2wasm;
+
+        let wasm = wat2wasm("(module (func (export \"f\")))").unwrap();
+        let path = env::temp_dir().join(format!("wasmer-compile-file-{}.wasm", process::id()));
+        fs::write(&path, &wasm).unwrap();
+        let module = compile_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            module.unwrap().to_binary().unwrap(),
+            compile(wasm).unwrap().to_binary().unwrap()
+        );
+        match compile_file(&path) {
+            Err(ErrorKind::IoError(ref error_path, io::ErrorKind::NotFound, _))
+                if *error_path == path.display().to_string() => {}
+            other => panic!("Expected a NotFound I/O error, received {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_function_bodies() {
+        use wabt::wat2wasm;
+
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func))
+              (func (result i32) (local i64) i32.const 7))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        let bodies: Vec<(usize, &[u8])> = module.function_bodies().collect();
+        // One i64 local, i32.const 7, end
+        assert_eq!(bodies, vec![(1, &[0x01, 0x01, 0x7e, 0x41, 0x07, 0x0b][..])]);
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn test_clif_ir() {
+        use cranelift_entity::EntityRef;
+        use cranelift_wasm::FuncIndex;
+        use wabt::wat2wasm;
+
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (import "env" "f" (func))
+              (func (param i32) (result i32)
+                get_local 0
+                i32.const 1
+                i32.add))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        assert_eq!(module.clif_ir(FuncIndex::new(0)), None);
+        let ir = module.clif_ir(FuncIndex::new(1)).unwrap();
+        assert!(ir.contains("iadd"));
+        assert_eq!(module.clif_ir(FuncIndex::new(2)), None);
+    }
+
+    #[test]
+    fn test_validate_exports() {
+        use super::super::errors::ErrorKind;
+        use super::Export;
+        use cranelift_entity::EntityRef;
+        use cranelift_wasm::{FuncIndex, GlobalIndex, MemoryIndex, TableIndex};
+        use wabt::wat2wasm;
+
+        let wasm = wat2wasm(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (table (export "table") 1 anyfunc)
+              (global (export "global") i32 (i32.const 0))
+              (func (export "f")))
+            "#,
+        ).unwrap();
+        let module = compile(wasm).unwrap();
+        assert!(module.validate().is_ok());
+
+        let exports = vec![
+            Export::Function(FuncIndex::new(1)),
+            Export::Table(TableIndex::new(1)),
+            Export::Memory(MemoryIndex::new(1)),
+            Export::Global(GlobalIndex::new(1)),
+        ];
+        for export in exports {
+            let mut broken = compile(module.to_binary().unwrap()).unwrap();
+            broken.info.exports.insert("broken".to_string(), export);
+            match broken.validate() {
+                Err(ErrorKind::ExportIndexOutOfRange(ref name, 1)) if name == "broken" => {}
+                other => panic!("Expected an out of range export, received {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_for_target() {
+        use super::super::compile_for_target;
+        use super::super::errors::ErrorKind;
+        use super::ModuleOptions;
+        use std::str::FromStr;
+        use target_lexicon::Triple;
+        use wabt::wat2wasm;
+
+        let wasm = wat2wasm("(module (func (export \"f\")))").unwrap();
+        let target = "x86_64-unknown-linux-gnu";
+        let module = compile_for_target(wasm.clone(), target, ModuleOptions::default()).unwrap();
+        assert_eq!(module.info.triple, Some(Triple::from_str(target).unwrap()));
+
+        // There is no WebAssembly backend
+        match compile_for_target(wasm.clone(), "wasm32-unknown-unknown", ModuleOptions::default()) {
+            Err(ErrorKind::CompileError(ref reason)) if reason.contains("Unsupported target") => {}
+            other => panic!("Expected an unsupported target, received {:?}", other.err()),
+        }
+        match compile_for_target(wasm, "not a triple", ModuleOptions::default()) {
+            Err(ErrorKind::CompileError(ref reason)) if reason.contains("Invalid target triple") => {}
+            other => panic!("Expected an invalid triple, received {:?}", other.err()),
+        }
+    }
+}
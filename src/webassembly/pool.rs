@@ -0,0 +1,129 @@
+//! Admission control for the instances of a module: an `InstancePool`
+//! caps the number of instances of its module that are alive at once.
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::errors::ErrorKind;
+use super::import_object::ImportObject;
+use super::instance::{Instance, InstanceOptions};
+use super::module::Module;
+
+/// Creates the instances of a shared module, failing with `PoolExhausted`
+/// when `max_instances` of them are already alive. The slot of an instance
+/// is released when it's dropped.
+pub struct InstancePool {
+    module: Arc<Module>,
+    max_instances: usize,
+    live_instances: Arc<AtomicUsize>,
+}
+
+impl InstancePool {
+    pub fn new(module: Arc<Module>, max_instances: usize) -> Self {
+        InstancePool {
+            module,
+            max_instances,
+            live_instances: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns the module the instances are created from.
+    pub fn module(&self) -> &Arc<Module> {
+        &self.module
+    }
+
+    /// Returns the number of instances of the pool that are alive.
+    pub fn live_instances(&self) -> usize {
+        self.live_instances.load(Ordering::SeqCst)
+    }
+
+    /// Creates an instance of the module, if the pool has a free slot.
+    pub fn instantiate(
+        &self,
+        import_object: ImportObject<&str, &str>,
+        options: InstanceOptions,
+    ) -> Result<PooledInstance, ErrorKind> {
+        if self.live_instances.fetch_add(1, Ordering::SeqCst) >= self.max_instances {
+            self.live_instances.fetch_sub(1, Ordering::SeqCst);
+            return Err(ErrorKind::PoolExhausted(self.max_instances));
+        }
+        match Instance::new(&self.module, import_object, options) {
+            Ok(instance) => Ok(PooledInstance {
+                instance,
+                live_instances: self.live_instances.clone(),
+            }),
+            Err(e) => {
+                self.live_instances.fetch_sub(1, Ordering::SeqCst);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// An instance created by an `InstancePool`, which releases its slot
+/// in the pool when dropped.
+pub struct PooledInstance {
+    instance: Instance,
+    live_instances: Arc<AtomicUsize>,
+}
+
+impl Deref for PooledInstance {
+    type Target = Instance;
+
+    fn deref(&self) -> &Instance {
+        &self.instance
+    }
+}
+
+impl DerefMut for PooledInstance {
+    fn deref_mut(&mut self) -> &mut Instance {
+        &mut self.instance
+    }
+}
+
+impl Drop for PooledInstance {
+    fn drop(&mut self) {
+        self.live_instances.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::compile;
+    use super::super::errors::ErrorKind;
+    use super::super::import_object::ImportObject;
+    use super::super::instance::InstanceOptions;
+    use super::InstancePool;
+    use cranelift_codegen::isa;
+    use cranelift_codegen::settings;
+    use std::sync::Arc;
+    use wabt::wat2wasm;
+
+    fn options() -> InstanceOptions {
+        let isa = isa::lookup(triple!("x86_64"))
+            .unwrap()
+            .finish(settings::Flags::new(settings::builder()));
+        InstanceOptions::new(isa, true)
+    }
+
+    #[test]
+    fn test_pool_limit() {
+        let wasm = wat2wasm("(module (func (export \"f\")))").unwrap();
+        let pool = InstancePool::new(Arc::new(compile(wasm).unwrap()), 2);
+
+        let first = pool.instantiate(ImportObject::new(), options()).unwrap();
+        let second = pool.instantiate(ImportObject::new(), options()).unwrap();
+        match pool.instantiate(ImportObject::new(), options()) {
+            Err(ErrorKind::PoolExhausted(2)) => {}
+            _ => panic!("the pool should be exhausted"),
+        }
+        assert_eq!(pool.live_instances(), 2);
+
+        drop(first);
+        let third = pool.instantiate(ImportObject::new(), options()).unwrap();
+        assert!(third.execute_fn(pool.module(), "f", &[]).is_ok());
+        drop(second);
+        drop(third);
+        assert_eq!(pool.live_instances(), 0);
+    }
+}
@@ -0,0 +1,203 @@
+//! Recording of the time spent in every local function during execution,
+//! as a tree of calls that can be exported as collapsed stacks (the input
+//! format of `inferno` and FlameGraph).
+use cranelift_wasm::FuncIndex;
+use std::time::{Duration, Instant};
+
+use super::module::Module;
+
+/// The calls to a function from the same chain of callers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileNode {
+    pub func_index: FuncIndex,
+    /// The number of calls that returned (or trapped)
+    pub calls: u64,
+    /// The total time spent in the calls, including the callees
+    pub time: Duration,
+    pub children: Vec<ProfileNode>,
+}
+
+impl ProfileNode {
+    /// Returns the time spent in the function itself, excluding the callees.
+    pub fn self_time(&self) -> Duration {
+        let children: Duration = self
+            .children
+            .iter()
+            .fold(Duration::new(0, 0), |total, child| total + child.time);
+        self.time.checked_sub(children).unwrap_or_else(|| Duration::new(0, 0))
+    }
+}
+
+/// The calls recorded while profiling, by chain of callers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileTree {
+    /// The functions called from the host
+    pub roots: Vec<ProfileNode>,
+}
+
+impl ProfileTree {
+    /// Returns the self time of every chain of calls in the collapsed stack
+    /// format (`<caller>;<callee> <microseconds>`, one chain per line).
+    pub fn collapsed_stacks(&self, module: &Module) -> String {
+        fn write_node(node: &ProfileNode, prefix: &str, module: &Module, out: &mut String) {
+            let stack = if prefix.is_empty() {
+                module.function_name(node.func_index)
+            } else {
+                format!("{};{}", prefix, module.function_name(node.func_index))
+            };
+            let self_time = node.self_time();
+            let micros = self_time.as_secs() * 1_000_000 + u64::from(self_time.subsec_micros());
+            out.push_str(&format!("{} {}\n", stack, micros));
+            for child in &node.children {
+                write_node(child, &stack, module, out);
+            }
+        }
+
+        let mut out = String::new();
+        for root in &self.roots {
+            write_node(root, "", module, &mut out);
+        }
+        out
+    }
+}
+
+struct RecordedNode {
+    func_index: FuncIndex,
+    calls: u64,
+    time: Duration,
+    children: Vec<usize>,
+}
+
+/// Builds the `ProfileTree` from the entries and exits of the functions.
+#[derive(Default)]
+pub struct Profiler {
+    enabled: bool,
+    nodes: Vec<RecordedNode>,
+    roots: Vec<usize>,
+    /// The node and entry time of the functions being executed
+    stack: Vec<(usize, Instant)>,
+}
+
+impl Profiler {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Returns the number of functions being executed.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn enter(&mut self, func_index: FuncIndex) {
+        if !self.enabled {
+            return;
+        }
+        let existing = {
+            let siblings = match self.stack.last() {
+                Some(&(parent, _)) => &self.nodes[parent].children,
+                None => &self.roots,
+            };
+            siblings
+                .iter()
+                .cloned()
+                .find(|node| self.nodes[*node].func_index == func_index)
+        };
+        let node = match existing {
+            Some(node) => node,
+            None => {
+                let node = self.nodes.len();
+                self.nodes.push(RecordedNode {
+                    func_index,
+                    calls: 0,
+                    time: Duration::new(0, 0),
+                    children: Vec::new(),
+                });
+                match self.stack.last() {
+                    Some(&(parent, _)) => self.nodes[parent].children.push(node),
+                    None => self.roots.push(node),
+                }
+                node
+            }
+        };
+        self.stack.push((node, Instant::now()));
+    }
+
+    pub fn exit(&mut self) {
+        if let Some((node, entry)) = self.stack.pop() {
+            let node = &mut self.nodes[node];
+            node.calls += 1;
+            node.time += entry.elapsed();
+        }
+    }
+
+    /// Exits the functions above `depth`, whose exits were skipped by a trap.
+    pub fn unwind(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            self.exit();
+        }
+    }
+
+    /// Returns the calls recorded so far and starts a new profile.
+    pub fn take(&mut self) -> ProfileTree {
+        fn build(nodes: &[RecordedNode], node: usize) -> ProfileNode {
+            let recorded = &nodes[node];
+            ProfileNode {
+                func_index: recorded.func_index,
+                calls: recorded.calls,
+                time: recorded.time,
+                children: recorded
+                    .children
+                    .iter()
+                    .map(|child| build(nodes, *child))
+                    .collect(),
+            }
+        }
+
+        let tree = ProfileTree {
+            roots: self.roots.iter().map(|root| build(&self.nodes, *root)).collect(),
+        };
+        self.nodes.clear();
+        self.roots.clear();
+        self.stack.clear();
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profiler;
+    use cranelift_entity::EntityRef;
+    use cranelift_wasm::FuncIndex;
+
+    #[test]
+    fn test_profiler_tree() {
+        let mut profiler = Profiler::default();
+        // Disabled profilers don't record anything
+        profiler.enter(FuncIndex::new(0));
+        profiler.exit();
+        assert!(profiler.take().roots.is_empty());
+
+        profiler.set_enabled(true);
+        for _ in 0..2 {
+            profiler.enter(FuncIndex::new(0));
+            profiler.enter(FuncIndex::new(1));
+            profiler.exit();
+            profiler.exit();
+        }
+        // A trap in the callee skips both exits
+        profiler.enter(FuncIndex::new(0));
+        profiler.enter(FuncIndex::new(2));
+        profiler.unwind(0);
+
+        let tree = profiler.take();
+        assert_eq!(tree.roots.len(), 1);
+        assert_eq!(tree.roots[0].calls, 3);
+        let children: Vec<_> = tree.roots[0]
+            .children
+            .iter()
+            .map(|child| (child.func_index.index(), child.calls))
+            .collect();
+        assert_eq!(children, vec![(1, 2), (2, 1)]);
+        assert!(profiler.take().roots.is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+//! Detection of the post-MVP WebAssembly proposals used by a module,
+//! by looking at its signatures, globals, memories and function bodies.
+use super::module::Module;
+
+/// The post-MVP proposals used by a module.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProposalSet {
+    pub sign_extension: bool,
+    pub saturating_float_to_int: bool,
+    pub bulk_memory: bool,
+    pub reference_types: bool,
+    pub simd: bool,
+    pub threads: bool,
+    pub tail_call: bool,
+    pub multi_value: bool,
+    pub mutable_globals: bool,
+}
+
+impl ProposalSet {
+    /// Returns true if the module only uses MVP features
+    pub fn is_mvp(&self) -> bool {
+        *self == ProposalSet::default()
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let byte = self.data.get(self.position).cloned();
+        self.position += 1;
+        byte
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.position += len;
+        if self.position <= self.data.len() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Reads a LEB128 integer of any size and signedness
+    fn var_u64(&mut self) -> Option<u64> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                result |= u64::from(byte & 0x7f) << shift;
+            }
+            if byte & 0x80 == 0 {
+                return Some(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn var_uint(&mut self) -> Option<()> {
+        self.var_u64().map(|_| ())
+    }
+}
+
+/// Flags the proposals used by the operators of a function body. The body
+/// is only scanned until an operator whose encoding is unknown is found.
+fn scan_function_body(body: &[u8], proposals: &mut ProposalSet) -> Option<()> {
+    let mut reader = Reader {
+        data: body,
+        position: 0,
+    };
+    let local_decls = reader.var_u64()?;
+    for _ in 0..local_decls {
+        reader.var_uint()?;
+        reader.u8()?;
+    }
+
+    while reader.position < body.len() {
+        match reader.u8()? {
+            // block, loop, if
+            0x02..=0x04 => {
+                let block_type = reader.u8()?;
+                // Block types other than empty and value types are type indexes
+                if block_type != 0x40 && (block_type < 0x7c || block_type > 0x7f) {
+                    proposals.multi_value = true;
+                    return None;
+                }
+            }
+            // br, br_if, call, get_local, set_local, tee_local, get_global, set_global
+            0x0c | 0x0d | 0x10 | 0x20..=0x24 => reader.var_uint()?,
+            // br_table
+            0x0e => {
+                let targets = reader.var_u64()?;
+                for _ in 0..=targets {
+                    reader.var_uint()?;
+                }
+            }
+            // call_indirect
+            0x11 => {
+                reader.var_uint()?;
+                reader.var_uint()?;
+            }
+            // return_call, return_call_indirect
+            0x12 | 0x13 => {
+                proposals.tail_call = true;
+                return None;
+            }
+            // typed select, table.get, table.set, ref.null, ref.is_null, ref.func
+            0x1c | 0x25 | 0x26 | 0xd0..=0xd2 => {
+                proposals.reference_types = true;
+                return None;
+            }
+            // loads and stores
+            0x28..=0x3e => {
+                reader.var_uint()?;
+                reader.var_uint()?;
+            }
+            // memory.size, memory.grow
+            0x3f | 0x40 => reader.skip(1)?,
+            // i32.const, i64.const
+            0x41 | 0x42 => reader.var_uint()?,
+            // f32.const
+            0x43 => reader.skip(4)?,
+            // f64.const
+            0x44 => reader.skip(8)?,
+            // sign extension operators
+            0xc0..=0xc4 => proposals.sign_extension = true,
+            0xfc => match reader.var_u64()? {
+                0..=7 => proposals.saturating_float_to_int = true,
+                // memory.init, table.init, table.copy
+                8 | 12 | 14 => {
+                    proposals.bulk_memory = true;
+                    reader.var_uint()?;
+                    reader.var_uint()?;
+                }
+                // data.drop, memory.copy, memory.fill, elem.drop
+                9 | 11 | 13 => {
+                    proposals.bulk_memory = true;
+                    reader.var_uint()?;
+                }
+                10 => {
+                    proposals.bulk_memory = true;
+                    reader.skip(2)?;
+                }
+                _ => return None,
+            },
+            0xfd => {
+                proposals.simd = true;
+                return None;
+            }
+            0xfe => {
+                proposals.threads = true;
+                return None;
+            }
+            // The rest of the MVP operators have no immediates
+            0x00..=0xbf => {}
+            _ => return None,
+        }
+    }
+    Some(())
+}
+
+/// Returns the post-MVP proposals used by the module.
+pub fn used_proposals(module: &Module) -> ProposalSet {
+    let info = &module.info;
+    let mut proposals = ProposalSet::default();
+
+    proposals.multi_value = info
+        .signatures
+        .iter()
+        .any(|signature| signature.returns.len() > 1);
+
+    proposals.threads = info.memories.iter().any(|memory| memory.entity.shared);
+
+    // Importing or exporting mutable globals is part of the mutable-globals proposal
+    proposals.mutable_globals = info.globals.iter().any(|global| {
+        global.entity.mutability
+            && (global.import_name.is_some() || !global.export_names.is_empty())
+    });
+
+    for body in &module.func_bytecode {
+        scan_function_body(body, &mut proposals);
+    }
+    proposals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scan_function_body, ProposalSet};
+
+    #[test]
+    fn test_scan_function_body() {
+        let mut proposals = ProposalSet::default();
+        // (local i32) get_local 0 i32.extend8_s i32.const -1 i32.add end
+        let body = [0x01, 0x01, 0x7f, 0x20, 0x00, 0xc0, 0x41, 0x7f, 0x6a, 0x0b];
+        assert_eq!(scan_function_body(&body, &mut proposals), Some(()));
+        assert!(proposals.sign_extension);
+        assert!(!proposals.saturating_float_to_int);
+
+        let mut proposals = ProposalSet::default();
+        // f32.const 0 i32.trunc_sat_f32_s drop end
+        let body = [0x00, 0x43, 0x00, 0x00, 0x00, 0x00, 0xfc, 0x00, 0x1a, 0x0b];
+        assert_eq!(scan_function_body(&body, &mut proposals), Some(()));
+        assert!(proposals.saturating_float_to_int);
+        assert!(!proposals.is_mvp());
+    }
+}
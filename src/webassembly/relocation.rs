@@ -27,6 +27,12 @@ pub enum RelocationType {
     LibCall(LibCall),
     GrowMemory,
     CurrentMemory,
+    CallStackExhausted,
+    ShadowCheck,
+    OutOfGas,
+    IntegerOverflow,
+    ProfileEnter,
+    ProfileExit,
 }
 
 /// Implementation of a relocation sink that just saves all the information for later
@@ -70,6 +76,12 @@ impl binemit::RelocSink for RelocSink {
                 let relocation_type = match name.as_str() {
                     "current_memory" => RelocationType::CurrentMemory,
                     "grow_memory" => RelocationType::GrowMemory,
+                    "call_stack_exhausted" => RelocationType::CallStackExhausted,
+                    "shadow_check" => RelocationType::ShadowCheck,
+                    "out_of_gas" => RelocationType::OutOfGas,
+                    "integer_overflow" => RelocationType::IntegerOverflow,
+                    "profile_enter" => RelocationType::ProfileEnter,
+                    "profile_exit" => RelocationType::ProfileExit,
                     _ => RelocationType::Intrinsic(name),
                 };
                 self.func_relocs.push(Relocation {
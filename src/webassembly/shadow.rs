@@ -0,0 +1,100 @@
+//! A shadow of the allocations of a module heap, for catching the memory
+//! accesses to freed or never allocated regions (like ASan does).
+//!
+//! The host tells which regions are allocated (usually from the `malloc`
+//! and `free` imports it provides), and the instance checks every access
+//! that falls inside the heap range against them.
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::errors::ErrorKind;
+
+#[derive(Debug)]
+pub struct ShadowHeap {
+    /// The range of the linear memory handed out by the allocator
+    range: Range<u32>,
+    /// The length of each allocation, by start address
+    allocations: BTreeMap<u32, u32>,
+}
+
+impl ShadowHeap {
+    pub fn new(range: Range<u32>) -> Self {
+        ShadowHeap {
+            range,
+            allocations: BTreeMap::new(),
+        }
+    }
+
+    /// Marks `address..address + len` as allocated.
+    pub fn allocate(&mut self, address: u32, len: u32) -> Result<(), ErrorKind> {
+        let end = u64::from(address) + u64::from(len);
+        if address < self.range.start || end > u64::from(self.range.end) {
+            return Err(ErrorKind::RuntimeError(format!(
+                "the allocation {:#x}..{:#x} is outside of the heap",
+                address, end
+            )));
+        }
+        let overlaps = self
+            .allocations
+            .range(..end as u32)
+            .next_back()
+            .map_or(false, |(start, allocation_len)| {
+                u64::from(*start) + u64::from(*allocation_len) > u64::from(address)
+            });
+        if overlaps {
+            return Err(ErrorKind::RuntimeError(format!(
+                "the allocation at {:#x} overlaps a live allocation",
+                address
+            )));
+        }
+        self.allocations.insert(address, len);
+        Ok(())
+    }
+
+    /// Marks the allocation starting at `address` as freed.
+    pub fn free(&mut self, address: u32) -> Result<(), ErrorKind> {
+        self.allocations.remove(&address).map(|_| ()).ok_or_else(|| {
+            ErrorKind::RuntimeError(format!(
+                "freeing {:#x}, which isn't the start of a live allocation",
+                address
+            ))
+        })
+    }
+
+    /// Returns true if an access of `size` bytes at `address` is allowed:
+    /// either it's outside of the heap, or within a single live allocation.
+    pub fn check(&self, address: u64, size: u64) -> bool {
+        let end = address + size;
+        if end <= u64::from(self.range.start) || address >= u64::from(self.range.end) {
+            return true;
+        }
+        self.allocations
+            .range(..=address as u32)
+            .next_back()
+            .map_or(false, |(start, len)| end <= u64::from(*start) + u64::from(*len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShadowHeap;
+
+    #[test]
+    fn test_shadow_heap() {
+        let mut heap = ShadowHeap::new(1024..4096);
+        // Outside of the heap (e.g. the stack or the static data)
+        assert!(heap.check(16, 4));
+        assert!(!heap.check(1024, 4));
+
+        heap.allocate(1024, 16).unwrap();
+        assert!(heap.allocate(1032, 16).is_err());
+        assert!(heap.check(1024, 8));
+        assert!(heap.check(1036, 4));
+        // Straddling the end of the allocation
+        assert!(!heap.check(1036, 8));
+
+        heap.free(1024).unwrap();
+        assert!(!heap.check(1024, 4));
+        assert!(heap.free(1024).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! The traps that interrupt the execution of WebAssembly code.
+//! Each trap has a stable `TrapCode`, and a human readable message that
+//! can be customized (e.g. localized) with `set_trap_message_formatter`.
+use nix::libc::c_int;
+use nix::sys::signal::{Signal, SIGBUS, SIGFPE, SIGILL, SIGSEGV};
+use std::cell::RefCell;
+use std::fmt;
+
+/// The kind of a trap. Most traps are detected through the signal raised
+/// by the faulting instruction, so there is one kind per handled signal.
+/// The rest are raised by the runtime helpers called from the compiled code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrapCode {
+    /// `SIGFPE`, raised by an integer division by zero or overflow
+    FloatingPointException,
+    /// `SIGILL`, raised by the trap instructions emitted by Cranelift
+    /// (e.g. for `unreachable`)
+    IllegalInstruction,
+    /// `SIGSEGV`, raised by an out of bounds memory access
+    SegmentationViolation,
+    /// `SIGBUS`
+    BusError,
+    /// A `memory.grow` failed, with `trap_on_grow_failure` enabled
+    MemoryGrowFailed,
+    /// The calls went deeper than the `max_call_depth` of the instance
+    CallStackExhausted,
+    /// An access to a freed or never allocated region of the shadow heap
+    InvalidHeapAccess,
+    /// A metered call used more gas than its limit
+    OutOfGas,
+    /// A signed integer operation overflowed, with `trap_on_int_overflow` enabled
+    IntegerOverflow,
+    /// Any other signal
+    Unknown,
+}
+
+/// The value unwound with, in place of a signal number, when a failed
+/// `memory.grow` traps.
+pub const MEMORY_GROW_FAILED: c_int = -1;
+
+/// The value unwound with when the calls go deeper than `max_call_depth`.
+pub const CALL_STACK_EXHAUSTED: c_int = -2;
+
+/// The value unwound with when an access isn't allowed by the shadow heap.
+pub const INVALID_HEAP_ACCESS: c_int = -3;
+
+/// The value unwound with when a metered call runs out of gas.
+pub const OUT_OF_GAS: c_int = -4;
+
+/// The value unwound with when a signed integer operation overflows.
+pub const INTEGER_OVERFLOW: c_int = -5;
+
+pub type TrapMessageFormatter = Box<Fn(TrapCode) -> String>;
+
+thread_local! {
+    static TRAP_MESSAGE_FORMATTER: RefCell<Option<TrapMessageFormatter>> = RefCell::new(None);
+}
+
+/// Sets the function used on the current thread to render the message of
+/// each trap kind, or restores the default English messages with `None`.
+pub fn set_trap_message_formatter(formatter: Option<TrapMessageFormatter>) {
+    TRAP_MESSAGE_FORMATTER.with(|f| *f.borrow_mut() = formatter);
+}
+
+impl TrapCode {
+    pub fn from_signal(signum: c_int) -> Self {
+        match signum {
+            MEMORY_GROW_FAILED => return TrapCode::MemoryGrowFailed,
+            CALL_STACK_EXHAUSTED => return TrapCode::CallStackExhausted,
+            INVALID_HEAP_ACCESS => return TrapCode::InvalidHeapAccess,
+            OUT_OF_GAS => return TrapCode::OutOfGas,
+            INTEGER_OVERFLOW => return TrapCode::IntegerOverflow,
+            _ => {}
+        }
+        match Signal::from_c_int(signum) {
+            Ok(SIGFPE) => TrapCode::FloatingPointException,
+            Ok(SIGILL) => TrapCode::IllegalInstruction,
+            Ok(SIGSEGV) => TrapCode::SegmentationViolation,
+            Ok(SIGBUS) => TrapCode::BusError,
+            _ => TrapCode::Unknown,
+        }
+    }
+
+    /// Returns the default description of the trap.
+    pub fn default_message(&self) -> &'static str {
+        match self {
+            TrapCode::FloatingPointException => "floating-point exception",
+            TrapCode::IllegalInstruction => "illegal instruction",
+            TrapCode::SegmentationViolation => "segmentation violation",
+            TrapCode::BusError => "bus error",
+            TrapCode::MemoryGrowFailed => "failed to grow the memory",
+            TrapCode::CallStackExhausted => "call stack exhausted",
+            TrapCode::InvalidHeapAccess => "access to a freed or unallocated heap region",
+            TrapCode::OutOfGas => "out of gas",
+            TrapCode::IntegerOverflow => "integer overflow",
+            TrapCode::Unknown => "unknown trapped signal",
+        }
+    }
+
+    /// Returns the message of the trap, as rendered by the formatter of
+    /// the current thread if there is one.
+    pub fn message(&self) -> String {
+        TRAP_MESSAGE_FORMATTER.with(|f| match *f.borrow() {
+            Some(ref formatter) => formatter(*self),
+            None => format!("trap - {}", self.default_message()),
+        })
+    }
+}
+
+impl fmt::Display for TrapCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_trap_message_formatter, TrapCode};
+
+    #[test]
+    fn test_trap_message_formatter() {
+        let code = TrapCode::IllegalInstruction;
+        assert_eq!(code.message(), "trap - illegal instruction");
+        set_trap_message_formatter(Some(Box::new(|code| match code {
+            TrapCode::IllegalInstruction => "instruction illégale".to_string(),
+            _ => code.default_message().to_string(),
+        })));
+        assert_eq!(code.message(), "instruction illégale");
+        set_trap_message_formatter(None);
+        assert_eq!(code.message(), "trap - illegal instruction");
+    }
+}
@@ -1,12 +1,41 @@
 //! Utility functions for the webassembly library
 use super::instance::Instance;
 use std::mem::transmute;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
 
 /// Detect if a provided binary is a WASM file
 pub fn is_wasm_binary(binary: &Vec<u8>) -> bool {
     binary.starts_with(&[b'\0', b'a', b's', b'm'])
 }
 
+/// Overwrite the provided bytes with zeros. The writes are volatile, so
+/// they are not optimized away even if the data is never read again
+/// (as it happens when freeing memory that held secrets).
+pub fn zero_volatile(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Computes the 64-bit FNV-1a hash of the provided bytes. Unlike the
+/// standard library hashers, the result is stable across Rust versions,
+/// so it can be stored (e.g. in test snapshots).
+pub fn fnv1a_hash(data: &[u8]) -> u64 {
+    fnv1a_update(0xcbf2_9ce4_8422_2325, data)
+}
+
+/// Continues the FNV-1a `hash` with the provided bytes, for hashing
+/// data that isn't contiguous in memory.
+pub fn fnv1a_update(mut hash: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
 pub fn print_instance_offsets(instance: &Instance) {
     let instance_address = instance as *const _ as usize;
     let data_ptr = &instance.data_pointers;
@@ -0,0 +1,93 @@
+//! A webassembly::Value is a WebAssembly value passed to or returned
+//! from an exported function.
+use cranelift_codegen::ir::{types, Type};
+
+/// A WebAssembly value. Floats are stored as they are, not as their bits.
+///
+/// WebAssembly integers have no signedness: it's the instructions that
+/// interpret them as signed or unsigned. They are stored here as signed,
+/// but the bits are what matter, so use `as_u32` and `as_u64` to read back
+/// unsigned values (like sizes or pointers).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    /// Returns the Cranelift type of the value.
+    pub fn ty(&self) -> Type {
+        match self {
+            Value::I32(_) => types::I32,
+            Value::I64(_) => types::I64,
+            Value::F32(_) => types::F32,
+            Value::F64(_) => types::F64,
+        }
+    }
+
+    /// Returns the zero value of type `ty`, or `None` for non-WebAssembly types.
+    pub fn default_for(ty: Type) -> Option<Value> {
+        Value::from_bits(ty, 0)
+    }
+
+    /// Returns the value of an `I32` reinterpreted as unsigned.
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self {
+            Value::I32(x) => Some(x as u32),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an `I64` reinterpreted as unsigned.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::I64(x) => Some(x as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns the bits of the value, zero-extended to 64 bits.
+    pub fn to_bits(&self) -> u64 {
+        match *self {
+            Value::I32(x) => u64::from(x as u32),
+            Value::I64(x) => x as u64,
+            Value::F32(x) => u64::from(x.to_bits()),
+            Value::F64(x) => x.to_bits(),
+        }
+    }
+
+    /// Builds a value of type `ty` from its bits (the upper bits are
+    /// ignored for 32-bit types). Returns `None` for non-WebAssembly types.
+    pub fn from_bits(ty: Type, bits: u64) -> Option<Value> {
+        match ty {
+            types::I32 => Some(Value::I32(bits as u32 as i32)),
+            types::I64 => Some(Value::I64(bits as i64)),
+            types::F32 => Some(Value::F32(f32::from_bits(bits as u32))),
+            types::F64 => Some(Value::F64(f64::from_bits(bits))),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+    use cranelift_codegen::ir::types;
+
+    #[test]
+    fn test_as_unsigned() {
+        assert_eq!(Value::I32(-1).as_u32(), Some(u32::max_value()));
+        assert_eq!(Value::I64(-2).as_u64(), Some(u64::max_value() - 1));
+        assert_eq!(Value::I32(-1).as_u64(), None);
+        assert_eq!(Value::F32(1.0).as_u32(), None);
+    }
+
+    #[test]
+    fn test_default_for() {
+        assert_eq!(Value::default_for(types::I32), Some(Value::I32(0)));
+        assert_eq!(Value::default_for(types::F64), Some(Value::F64(0.0)));
+        assert_eq!(Value::default_for(types::B1), None);
+    }
+}